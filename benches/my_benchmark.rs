@@ -1,19 +1,128 @@
 use criterion::{criterion_group, criterion_main, Criterion};
-use massa_smt_bench::lsmtree::add_lsmtree_benches;
-use massa_smt_bench::monotree::add_monotree_benches;
-// use massa_smt_bench::sparse_merkle_tree::add_sparse_merkle_tree_benches;
+use massa_smt_bench::lsmtree::{
+    add_lsmtree_benches, add_lsmtree_block_benches, add_lsmtree_checkpoint_benches,
+    add_lsmtree_layout_benches, add_lsmtree_profile_benches, add_lsmtree_proof_benches,
+    add_lsmtree_tree_size_benches,
+};
+use massa_smt_bench::monotree::{
+    add_monotree_batch_benches, add_monotree_benches, add_monotree_block_benches,
+    add_monotree_checkpoint_benches, add_monotree_layout_benches, add_monotree_profile_benches,
+    add_monotree_proof_benches, add_monotree_pruning_benches, add_monotree_tree_size_benches,
+};
+use massa_smt_bench::sparse_merkle_tree::{
+    add_sparse_merkle_tree_benches, add_sparse_merkle_tree_block_benches,
+    add_sparse_merkle_tree_checkpoint_benches, add_sparse_merkle_tree_layout_benches,
+    add_sparse_merkle_tree_profile_benches, add_sparse_merkle_tree_proof_benches,
+    add_sparse_merkle_tree_snapshot_benches, add_sparse_merkle_tree_tree_size_benches,
+};
+use massa_smt_bench::suite::drop_fixtures;
 
+// The RocksDB stores are tuned by the profile named in SMT_BENCH_ROCKSDB_PROFILE,
+// one of "default", "read-optimized", "write-optimized" or "direct-io", recorded in the labels
+// The keys come from a seeded stream, set SMT_BENCH_SEED to the printed seed to re-run on the same keys
 const SAMPLE_SIZE: usize = 1000;
 const ELEMENTS_IN_TREE: usize = 10000;
 
+// Keys updated in one block, applied at once through each library bulk API
+const BLOCK_SIZES: [usize; 3] = [100, 1_000, 10_000];
+const BLOCK_SAMPLE_SIZE: usize = 100;
+
+// States kept by the versioned monotree, each prune walks the nodes of all of them
+const HISTORY_DEPTHS: [usize; 3] = [1, 10, 100];
+const PRUNING_SAMPLE_SIZE: usize = 100;
+
+// Updates applied after a checkpoint before rolling back, the size of the speculative blocks
+const ROLLBACK_UPDATES: [usize; 3] = [10, 100, 1_000];
+
+// Filling the largest trees takes minutes, fewer samples are taken on them
+const TREE_SIZE_SAMPLE_SIZE: usize = 100;
+const TREE_SIZES: [usize; 4] = [1_000, 10_000, 100_000, 1_000_000];
+
 // TODO    cw-merkle-tree
-// TODO    lsmtree
 
 pub fn all_frameworks(c: &mut Criterion) {
     add_monotree_benches(c, SAMPLE_SIZE, ELEMENTS_IN_TREE);
-    // add_sparse_merkle_tree_benches(c, SAMPLE_SIZE, ELEMENTS_IN_TREE);
+    add_sparse_merkle_tree_benches(c, SAMPLE_SIZE, ELEMENTS_IN_TREE);
     add_lsmtree_benches(c, SAMPLE_SIZE, ELEMENTS_IN_TREE);
 }
 
-criterion_group!(benches, all_frameworks);
+// The proof sizes are printed for blake3 only, every hash function gives 32 bytes digests
+// and the proof size does not depend on it
+pub fn all_proofs(c: &mut Criterion) {
+    add_monotree_proof_benches(c, SAMPLE_SIZE, ELEMENTS_IN_TREE);
+    add_sparse_merkle_tree_proof_benches(c, SAMPLE_SIZE, ELEMENTS_IN_TREE);
+    add_lsmtree_proof_benches(c, SAMPLE_SIZE, ELEMENTS_IN_TREE);
+}
+
+pub fn batching(c: &mut Criterion) {
+    add_monotree_batch_benches(c, SAMPLE_SIZE, ELEMENTS_IN_TREE);
+}
+
+// One RocksDB with two column families against two RocksDB instances
+pub fn rocksdb_layouts(c: &mut Criterion) {
+    add_monotree_layout_benches(c, SAMPLE_SIZE, ELEMENTS_IN_TREE);
+    add_sparse_merkle_tree_layout_benches(c, SAMPLE_SIZE, ELEMENTS_IN_TREE);
+    add_lsmtree_layout_benches(c, SAMPLE_SIZE, ELEMENTS_IN_TREE);
+}
+
+// Blocks of updates and removals through the bulk API, what a ledger commit does
+pub fn blocks(c: &mut Criterion) {
+    add_monotree_block_benches(c, BLOCK_SAMPLE_SIZE, ELEMENTS_IN_TREE, &BLOCK_SIZES);
+    add_sparse_merkle_tree_block_benches(c, BLOCK_SAMPLE_SIZE, ELEMENTS_IN_TREE, &BLOCK_SIZES);
+    add_lsmtree_block_benches(c, BLOCK_SAMPLE_SIZE, ELEMENTS_IN_TREE, &BLOCK_SIZES);
+}
+
+// Mixed reads and writes of the YCSB like profiles
+pub fn profiles(c: &mut Criterion) {
+    add_monotree_profile_benches(c, SAMPLE_SIZE, ELEMENTS_IN_TREE);
+    add_sparse_merkle_tree_profile_benches(c, SAMPLE_SIZE, ELEMENTS_IN_TREE);
+    add_lsmtree_profile_benches(c, SAMPLE_SIZE, ELEMENTS_IN_TREE);
+}
+
+// Trees saved once and loaded by the later runs, in memory and in RocksDB
+pub fn snapshots(c: &mut Criterion) {
+    add_sparse_merkle_tree_snapshot_benches(c, SAMPLE_SIZE, ELEMENTS_IN_TREE);
+}
+
+pub fn pruning(c: &mut Criterion) {
+    add_monotree_pruning_benches(c, PRUNING_SAMPLE_SIZE, ELEMENTS_IN_TREE, &HISTORY_DEPTHS);
+}
+
+pub fn checkpoints(c: &mut Criterion) {
+    add_monotree_checkpoint_benches(c, BLOCK_SAMPLE_SIZE, ELEMENTS_IN_TREE, &ROLLBACK_UPDATES);
+    add_sparse_merkle_tree_checkpoint_benches(
+        c,
+        BLOCK_SAMPLE_SIZE,
+        ELEMENTS_IN_TREE,
+        &ROLLBACK_UPDATES,
+    );
+    add_lsmtree_checkpoint_benches(c, BLOCK_SAMPLE_SIZE, ELEMENTS_IN_TREE, &ROLLBACK_UPDATES);
+}
+
+// The filled trees are shared by the targets above, free them before the large sweeps
+pub fn drop_filled_trees(_: &mut Criterion) {
+    drop_fixtures();
+}
+
+// How the operations cost grows with the number of entries in the tree
+pub fn tree_sizes(c: &mut Criterion) {
+    add_monotree_tree_size_benches(c, TREE_SIZE_SAMPLE_SIZE, &TREE_SIZES);
+    add_sparse_merkle_tree_tree_size_benches(c, TREE_SIZE_SAMPLE_SIZE, &TREE_SIZES);
+    add_lsmtree_tree_size_benches(c, TREE_SIZE_SAMPLE_SIZE, &TREE_SIZES);
+}
+
+criterion_group!(
+    benches,
+    all_frameworks,
+    all_proofs,
+    batching,
+    rocksdb_layouts,
+    blocks,
+    profiles,
+    snapshots,
+    pruning,
+    checkpoints,
+    drop_filled_trees,
+    tree_sizes
+);
 criterion_main!(benches);
@@ -0,0 +1,70 @@
+use monotree::Hash;
+
+/// Common interface over the benchmarked sparse merkle tree libraries
+///
+/// Keys and values are 32 bytes hashes, a removed or absent key has no value,
+/// and the root of an empty tree is all zeros.
+pub trait SmtBackend {
+    type Proof: Clone;
+    type Error: std::fmt::Debug;
+    type Checkpoint;
+
+    /// Whether removing a key gives back the root the tree had before inserting it
+    const REMOVE_RESTORES_ROOT: bool = true;
+
+    /// Whether `prove` gives a proof for a key that is not in the tree
+    const PROVES_ABSENCE: bool = true;
+
+    /// Whether `verify` accepts the proofs given by `prove`
+    const PROOFS_VERIFY: bool = true;
+
+    /// Whether the same entries give the same root whatever the order they are inserted in
+    const ROOT_IGNORES_ORDER: bool = true;
+
+    fn insert(&mut self, key: &Hash, value: &Hash) -> Result<(), Self::Error>;
+
+    fn get(&mut self, key: &Hash) -> Result<Option<Hash>, Self::Error>;
+
+    fn remove(&mut self, key: &Hash) -> Result<(), Self::Error>;
+
+    /// Removes a key known to be in the tree, skipping the checks `remove` needs for absent keys
+    fn remove_present(&mut self, key: &Hash) -> Result<(), Self::Error> {
+        self.remove(key)
+    }
+
+    fn root(&self) -> Hash;
+
+    fn prove(&mut self, key: &Hash) -> Result<Self::Proof, Self::Error>;
+
+    /// Checks the proof of `key` holding `value` against the current root
+    fn verify(&self, key: &Hash, value: &Hash, proof: Self::Proof) -> bool;
+
+    /// Checks the proof of `key` having no value against the current root
+    fn verify_absent(&self, key: &Hash, proof: Self::Proof) -> bool;
+
+    /// Inserts or updates all the entries, using the library bulk API when it has one
+    fn batch_update(&mut self, entries: &[(Hash, Hash)]) -> Result<(), Self::Error>;
+
+    /// Removes all the keys, using the library bulk API when it has one
+    fn batch_remove(&mut self, keys: &[Hash]) -> Result<(), Self::Error>;
+
+    /// Removes keys known to be in the tree, same as `remove_present` for `batch_remove`
+    fn batch_remove_present(&mut self, keys: &[Hash]) -> Result<(), Self::Error> {
+        self.batch_remove(keys)
+    }
+
+    /// Size of the proof once serialized, in bytes
+    fn proof_size(proof: &Self::Proof) -> usize;
+
+    /// Copy of the tree that can be changed without affecting this one
+    fn fork(&self) -> Self;
+
+    /// Saves the state of the tree, to go back to it after speculative updates
+    fn checkpoint(&mut self) -> Result<Self::Checkpoint, Self::Error>;
+
+    /// Gives back the state the tree had at `checkpoint`, the checkpoints taken after it are dropped
+    fn rollback(&mut self, checkpoint: Self::Checkpoint) -> Result<(), Self::Error>;
+
+    /// Keeps the current state, `checkpoint` and the ones taken after it are dropped
+    fn release(&mut self, checkpoint: Self::Checkpoint) -> Result<(), Self::Error>;
+}
@@ -0,0 +1,83 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Where the on-disk stores of the benchmarks are created
+pub const BENCH_DB_ROOT: &str = ".bench_db";
+
+/// Where the trees saved for later runs are kept, nothing removes them
+pub const SNAPSHOT_ROOT: &str = ".bench_snapshots";
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// Directory of an on-disk store, removed with all its content when dropped
+///
+/// The store using it has to be dropped first, declare it after the store in a struct.
+#[derive(Debug)]
+pub struct BenchDir {
+    path: PathBuf,
+}
+
+impl BenchDir {
+    /// Fresh directory under `BENCH_DB_ROOT`, unique to this process and call
+    pub fn new(name: &str) -> BenchDir {
+        let id = NEXT_DIR.fetch_add(1, Ordering::Relaxed);
+        let dirname = format!("{}_{}_{}", name, std::process::id(), id);
+        BenchDir::at(Path::new(BENCH_DB_ROOT).join(dirname))
+    }
+
+    /// Takes over `path`, panics if something is already in it
+    pub fn at(path: impl Into<PathBuf>) -> BenchDir {
+        let path = path.into();
+        if let Ok(mut entries) = fs::read_dir(&path) {
+            assert!(
+                entries.next().is_none(),
+                "benchmark directory {} is not empty, remove what a previous run left there",
+                path.display()
+            );
+        }
+        fs::create_dir_all(&path)
+            .unwrap_or_else(|err| panic!("cannot create {}: {}", path.display(), err));
+        BenchDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Path of a database inside the directory, as the monotree API takes it
+    pub fn db_path(&self, name: &str) -> String {
+        self.path.join(name).to_string_lossy().into_owned()
+    }
+}
+
+impl Drop for BenchDir {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_dir_all(&self.path) {
+            eprintln!("cannot remove {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BenchDir;
+
+    #[test]
+    fn removed_on_drop() {
+        let dir = BenchDir::new("bench_dir_test");
+        let path = dir.path().to_path_buf();
+        std::fs::write(path.join("file"), b"content").unwrap();
+        drop(dir);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    #[should_panic(expected = "is not empty")]
+    fn refuses_a_non_empty_directory() {
+        // Dropped while the panic unwinds, nothing is left behind
+        let taken = BenchDir::new("bench_dir_test");
+        std::fs::write(taken.path().join("leftover"), b"content").unwrap();
+        let _dir = BenchDir::at(taken.path());
+    }
+}
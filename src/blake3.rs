@@ -1,8 +1,17 @@
-use lsmtree::digest::generic_array::GenericArray;
+use lsmtree::digest::typenum::U32;
+use lsmtree::digest::{
+    FixedOutput, FixedOutputReset, HashMarker, Output, OutputSizeUser, Reset, Update,
+};
+
+use crate::hasher::SmtHasher;
 
 #[derive(Debug, Default, Clone)]
 pub struct Blake3SmtHasher(blake3::Hasher);
 
+impl SmtHasher for Blake3SmtHasher {
+    const NAME: &'static str = "blake3";
+}
+
 // MONOTREE
 
 impl monotree::hasher::Hasher for Blake3SmtHasher {
@@ -34,56 +43,184 @@ impl sparse_merkle_tree::traits::Hasher for Blake3SmtHasher {
     }
 }
 
-// LSMTREE
+// LSMTREE, `Digest` comes from the blanket implementation over these traits
 
-impl lsmtree::digest::OutputSizeUser for Blake3SmtHasher {
-    type OutputSize = lsmtree::digest::typenum::U32;
+impl OutputSizeUser for Blake3SmtHasher {
+    type OutputSize = U32;
 }
 
-impl lsmtree::digest::Digest for Blake3SmtHasher {
-    fn new() -> Self {
-        Blake3SmtHasher(blake3::Hasher::new())
-    }
-
-    fn new_with_prefix(_: impl AsRef<[u8]>) -> Self {
-        todo!()
-    }
-
-    fn update(&mut self, data: impl AsRef<[u8]>) {
-        self.0.update(data.as_ref());
-    }
-
-    fn chain_update(self, _: impl AsRef<[u8]>) -> Self {
-        todo!()
-    }
-
-    fn finalize(self) -> lsmtree::digest::Output<Self> {
-        GenericArray::clone_from_slice(self.0.finalize().as_bytes())
-    }
-
-    fn finalize_into(self, _: &mut lsmtree::digest::Output<Self>) {
-        unreachable!()
-    }
-
-    fn finalize_reset(&mut self) -> lsmtree::digest::Output<Self> {
-        unreachable!()
+impl Update for Blake3SmtHasher {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
     }
+}
 
-    fn finalize_into_reset(&mut self, _: &mut lsmtree::digest::Output<Self>) {
-        unreachable!()
+impl FixedOutput for Blake3SmtHasher {
+    fn finalize_into(self, out: &mut Output<Self>) {
+        out.copy_from_slice(self.0.finalize().as_bytes());
     }
+}
 
+impl Reset for Blake3SmtHasher {
     fn reset(&mut self) {
-        unreachable!()
+        self.0.reset();
     }
+}
 
-    fn output_size() -> usize {
-        <Self as lsmtree::digest::OutputSizeUser>::output_size()
+impl FixedOutputReset for Blake3SmtHasher {
+    fn finalize_into_reset(&mut self, out: &mut Output<Self>) {
+        out.copy_from_slice(self.0.finalize().as_bytes());
+        self.0.reset();
     }
+}
 
-    fn digest(data: impl AsRef<[u8]>) -> lsmtree::digest::Output<Self> {
-        let mut h = Self::new();
-        h.update(data);
-        h.finalize()
+impl HashMarker for Blake3SmtHasher {}
+
+#[cfg(test)]
+mod tests {
+    use lsmtree::digest::Digest;
+    use sparse_merkle_tree::traits::Hasher as _;
+
+    use super::Blake3SmtHasher;
+
+    // First 32 bytes of the `hash` field of the official BLAKE3 test_vectors.json,
+    // the input is the byte pattern 0, 1, ..., 250, 0, 1, ... of the given length
+    #[rustfmt::skip]
+    const TEST_VECTORS: [(usize, &str); 22] = [
+        (0, "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"),
+        (1, "2d3adedff11b61f14c886e35afa036736dcd87a74d27b5c1510225d0f592e213"),
+        (1023, "10108970eeda3eb932baac1428c7a2163b0e924c9a9e25b35bba72b28f70bd11"),
+        (1024, "42214739f095a406f3fc83deb889744ac00df831c10daa55189b5d121c855af7"),
+        (1025, "d00278ae47eb27b34faecf67b4fe263f82d5412916c1ffd97c8cb7fb814b8444"),
+        (2048, "e776b6028c7cd22a4d0ba182a8bf62205d2ef576467e838ed6f2529b85fba24a"),
+        (2049, "5f4d72f40d7a5f82b15ca2b2e44b1de3c2ef86c426c95c1af0b6879522563030"),
+        (3072, "b98cb0ff3623be03326b373de6b9095218513e64f1ee2edd2525c7ad1e5cffd2"),
+        (3073, "7124b49501012f81cc7f11ca069ec9226cecb8a2c850cfe644e327d22d3e1cd3"),
+        (4096, "015094013f57a5277b59d8475c0501042c0b642e531b0a1c8f58d2163229e969"),
+        (4097, "9b4052b38f1c5fc8b1f9ff7ac7b27cd242487b3d890d15c96a1c25b8aa0fb995"),
+        (5120, "9cadc15fed8b5d854562b26a9536d9707cadeda9b143978f319ab34230535833"),
+        (5121, "628bd2cb2004694adaab7bbd778a25df25c47b9d4155a55f8fbd79f2fe154cff"),
+        (6144, "3e2e5b74e048f3add6d21faab3f83aa44d3b2278afb83b80b3c35164ebeca205"),
+        (6145, "f1323a8631446cc50536a9f705ee5cb619424d46887f3c376c695b70e0f0507f"),
+        (7168, "61da957ec2499a95d6b8023e2b0e604ec7f6b50e80a9678b89d2628e99ada77a"),
+        (7169, "a003fc7a51754a9b3c7fae0367ab3d782dccf28855a03d435f8cfe74605e7817"),
+        (8192, "aae792484c8efe4f19e2ca7d371d8c467ffb10748d8a5a1ae579948f718a2a63"),
+        (8193, "bab6c09cb8ce8cf459261398d2e7aef35700bf488116ceb94a36d0f5f1b7bc3b"),
+        (16384, "f875d6646de28985646f34ee13be9a576fd515f76b5b0a26bb324735041ddde4"),
+        (31744, "62b6960e1a44bcc1eb1a611a8d6235b6b4b78f32e7abc4fb4c6cdcce94895c47"),
+        (102400, "bc3e3d41a1146b069abffad3c0d44860cf664390afce4d9661f7902e7943e085"),
+    ];
+
+    fn test_input(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn to_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn monotree_digest(input: &[u8]) -> [u8; 32] {
+        let hasher = <Blake3SmtHasher as monotree::Hasher>::new();
+        monotree::Hasher::digest(&hasher, input)
+    }
+
+    fn smt_digest(input: &[u8]) -> [u8; 32] {
+        let mut hasher = Blake3SmtHasher::default();
+        for b in input {
+            hasher.write_byte(*b);
+        }
+        hasher.finish().into()
+    }
+
+    #[test]
+    fn official_test_vectors() {
+        for (len, expected) in TEST_VECTORS {
+            let input = test_input(len);
+            assert_eq!(
+                to_hex(&Blake3SmtHasher::digest(&input)),
+                expected,
+                "lsmtree, len {}",
+                len
+            );
+            assert_eq!(
+                to_hex(&monotree_digest(&input)),
+                expected,
+                "monotree, len {}",
+                len
+            );
+            assert_eq!(
+                to_hex(&smt_digest(&input)),
+                expected,
+                "sparse-merkle-tree, len {}",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn smt_branch_hash_matches_other_traits() {
+        let left = [0x11; 32];
+        let right = [0x22; 32];
+        let mut hasher = Blake3SmtHasher::default();
+        hasher.write_byte(7);
+        hasher.write_h256(&left.into());
+        hasher.write_h256(&right.into());
+        let smt: [u8; 32] = hasher.finish().into();
+
+        let input = [&[7][..], &left[..], &right[..]].concat();
+        assert_eq!(smt, monotree_digest(&input));
+        assert_eq!(smt, <[u8; 32]>::from(Blake3SmtHasher::digest(&input)));
+    }
+
+    #[test]
+    fn incremental_and_prefixed_updates() {
+        let input = test_input(2049);
+        let expected = Blake3SmtHasher::digest(&input);
+        let (head, tail) = input.split_at(1000);
+
+        let mut hasher = <Blake3SmtHasher as Digest>::new();
+        Digest::update(&mut hasher, head);
+        Digest::update(&mut hasher, tail);
+        assert_eq!(hasher.finalize(), expected);
+
+        assert_eq!(
+            Blake3SmtHasher::new_with_prefix(head)
+                .chain_update(tail)
+                .finalize(),
+            expected
+        );
+        assert_eq!(
+            <Blake3SmtHasher as Digest>::new()
+                .chain_update(&input)
+                .finalize(),
+            expected
+        );
+    }
+
+    #[test]
+    fn finalize_into_and_reset() {
+        let input = test_input(1025);
+        let expected = Blake3SmtHasher::digest(&input);
+        let empty = Blake3SmtHasher::digest([]);
+
+        let mut out = Default::default();
+        Blake3SmtHasher::new_with_prefix(&input).finalize_into(&mut out);
+        assert_eq!(out, expected);
+
+        let mut hasher = Blake3SmtHasher::new_with_prefix(&input);
+        assert_eq!(hasher.finalize_reset(), expected);
+        assert_eq!(hasher.finalize_reset(), empty);
+
+        let mut hasher = Blake3SmtHasher::new_with_prefix(&input);
+        hasher.finalize_into_reset(&mut out);
+        assert_eq!(out, expected);
+        Digest::update(&mut hasher, &input);
+        assert_eq!(hasher.finalize(), expected);
+
+        let mut hasher = Blake3SmtHasher::new_with_prefix(b"discarded");
+        Digest::reset(&mut hasher);
+        Digest::update(&mut hasher, &input);
+        assert_eq!(hasher.finalize(), expected);
+        assert_eq!(<Blake3SmtHasher as Digest>::output_size(), 32);
     }
 }
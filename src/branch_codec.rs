@@ -0,0 +1,303 @@
+//! Byte encoding of the sparse-merkle-tree branch nodes, for the stores that persist them
+//!
+//! A branch is the codec version, then its left and right arms. Each arm is a tag followed
+//! by the fields of its `MergeValue` variant, all of a fixed length.
+
+use sparse_merkle_tree::merge::MergeValue;
+use sparse_merkle_tree::{BranchKey, BranchNode, H256};
+
+use crate::error::StoreError;
+
+/// Version heading every encoded branch, to change when the layout does
+pub const BRANCH_CODEC_VERSION: u8 = 1;
+
+/// Length of an encoded `BranchKey`, its height then its node key
+pub const BRANCH_KEY_LEN: usize = 33;
+
+const VALUE_TAG: u8 = 1;
+const MERGE_WITH_ZERO_TAG: u8 = 2;
+#[cfg(feature = "trie")]
+const SHORT_CUT_TAG: u8 = 3;
+
+/// Why some bytes are not an encoded branch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BranchCodecError {
+    UnsupportedVersion(u8),
+    UnknownTag {
+        tag: u8,
+        offset: usize,
+    },
+    /// The input ends before the `expected` bytes the branch needs
+    Truncated {
+        expected: usize,
+        actual: usize,
+    },
+    TrailingBytes(usize),
+}
+
+impl std::fmt::Display for BranchCodecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BranchCodecError::UnsupportedVersion(version) => {
+                write!(f, "unsupported branch codec version {}", version)
+            }
+            BranchCodecError::UnknownTag { tag, offset } => {
+                write!(f, "unknown branch arm tag {} at byte {}", tag, offset)
+            }
+            BranchCodecError::Truncated { expected, actual } => write!(
+                f,
+                "truncated branch, expected at least {} bytes actual {}",
+                expected, actual
+            ),
+            BranchCodecError::TrailingBytes(nb) => {
+                write!(f, "{} trailing bytes after branch", nb)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BranchCodecError {}
+
+impl From<BranchCodecError> for StoreError {
+    fn from(err: BranchCodecError) -> Self {
+        StoreError::Corrupted(err.to_string())
+    }
+}
+
+impl From<BranchCodecError> for sparse_merkle_tree::error::Error {
+    fn from(err: BranchCodecError) -> Self {
+        StoreError::from(err).into()
+    }
+}
+
+// Branches at different heights can share the same node key, the height has to be part of the key
+pub fn encode_branch_key(branch_key: &BranchKey) -> [u8; BRANCH_KEY_LEN] {
+    let mut key = [0; BRANCH_KEY_LEN];
+    key[0] = branch_key.height;
+    key[1..].copy_from_slice(branch_key.node_key.as_slice());
+    key
+}
+
+/// Inverse of `encode_branch_key`
+pub fn decode_branch_key(bytes: &[u8; BRANCH_KEY_LEN]) -> BranchKey {
+    let node_key: [u8; 32] = bytes[1..].try_into().expect("32 bytes node key");
+    BranchKey::new(bytes[0], node_key.into())
+}
+
+fn arm_len(arm: &MergeValue) -> usize {
+    match arm {
+        MergeValue::Value(_) => 1 + 32,
+        MergeValue::MergeWithZero { .. } => 1 + 32 + 32 + 1,
+        #[cfg(feature = "trie")]
+        MergeValue::ShortCut { .. } => 1 + 32 + 32 + 1,
+    }
+}
+
+/// Length of the encoding of `branch`, without encoding it
+pub fn encoded_branch_len(branch: &BranchNode) -> usize {
+    1 + arm_len(&branch.left) + arm_len(&branch.right)
+}
+
+fn encode_arm(arm: &MergeValue, bytes: &mut Vec<u8>) {
+    match arm {
+        MergeValue::Value(hash) => {
+            bytes.push(VALUE_TAG);
+            bytes.extend_from_slice(hash.as_slice());
+        }
+        MergeValue::MergeWithZero {
+            base_node,
+            zero_bits,
+            zero_count,
+        } => {
+            bytes.push(MERGE_WITH_ZERO_TAG);
+            bytes.extend_from_slice(base_node.as_slice());
+            bytes.extend_from_slice(zero_bits.as_slice());
+            bytes.push(*zero_count);
+        }
+        #[cfg(feature = "trie")]
+        MergeValue::ShortCut { key, value, height } => {
+            bytes.push(SHORT_CUT_TAG);
+            bytes.extend_from_slice(key.as_slice());
+            bytes.extend_from_slice(value.as_slice());
+            bytes.push(*height);
+        }
+    }
+}
+
+pub fn encode_branch(branch: &BranchNode) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(encoded_branch_len(branch));
+    bytes.push(BRANCH_CODEC_VERSION);
+    encode_arm(&branch.left, &mut bytes);
+    encode_arm(&branch.right, &mut bytes);
+    bytes
+}
+
+/// Cursor over an encoded branch, checking each read stays in the input
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], BranchCodecError> {
+        let end = self.offset + len;
+        let taken = self
+            .bytes
+            .get(self.offset..end)
+            .ok_or(BranchCodecError::Truncated {
+                expected: end,
+                actual: self.bytes.len(),
+            })?;
+        self.offset = end;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, BranchCodecError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn hash(&mut self) -> Result<H256, BranchCodecError> {
+        let hash: [u8; 32] = self.take(32)?.try_into().expect("32 bytes taken");
+        Ok(hash.into())
+    }
+
+    fn arm(&mut self) -> Result<MergeValue, BranchCodecError> {
+        let offset = self.offset;
+        match self.byte()? {
+            VALUE_TAG => Ok(MergeValue::Value(self.hash()?)),
+            MERGE_WITH_ZERO_TAG => Ok(MergeValue::MergeWithZero {
+                base_node: self.hash()?,
+                zero_bits: self.hash()?,
+                zero_count: self.byte()?,
+            }),
+            #[cfg(feature = "trie")]
+            SHORT_CUT_TAG => Ok(MergeValue::ShortCut {
+                key: self.hash()?,
+                value: self.hash()?,
+                height: self.byte()?,
+            }),
+            tag => Err(BranchCodecError::UnknownTag { tag, offset }),
+        }
+    }
+}
+
+/// Decodes a branch encoded by `encode_branch`, any byte missing or left over is an error
+pub fn decode_branch(bytes: &[u8]) -> Result<BranchNode, BranchCodecError> {
+    let mut reader = Reader { bytes, offset: 0 };
+    let version = reader.byte()?;
+    if version != BRANCH_CODEC_VERSION {
+        return Err(BranchCodecError::UnsupportedVersion(version));
+    }
+    let left = reader.arm()?;
+    let right = reader.arm()?;
+    match bytes.len() - reader.offset {
+        0 => Ok(BranchNode { left, right }),
+        trailing => Err(BranchCodecError::TrailingBytes(trailing)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::proptest;
+    use sparse_merkle_tree::merge::MergeValue;
+    use sparse_merkle_tree::{BranchNode, H256};
+
+    use super::{
+        decode_branch, encode_branch, encoded_branch_len, BranchCodecError, BRANCH_CODEC_VERSION,
+    };
+    use crate::store_model::branch_node;
+
+    fn merge_with_zero() -> MergeValue {
+        MergeValue::MergeWithZero {
+            base_node: H256::from([1; 32]),
+            zero_bits: H256::from([2; 32]),
+            zero_count: 3,
+        }
+    }
+
+    fn branch() -> BranchNode {
+        BranchNode {
+            left: MergeValue::Value(H256::from([4; 32])),
+            right: merge_with_zero(),
+        }
+    }
+
+    #[test]
+    fn every_variant_round_trips() {
+        let arms = [MergeValue::zero(), merge_with_zero()];
+        #[cfg(feature = "trie")]
+        let arms = [
+            &arms[..],
+            &[MergeValue::ShortCut {
+                key: H256::from([5; 32]),
+                value: H256::from([6; 32]),
+                height: 7,
+            }],
+        ]
+        .concat();
+        for left in arms.iter() {
+            for right in arms.iter() {
+                let branch = BranchNode {
+                    left: left.clone(),
+                    right: right.clone(),
+                };
+                let bytes = encode_branch(&branch);
+                assert_eq!(bytes.len(), encoded_branch_len(&branch));
+                assert_eq!(decode_branch(&bytes), Ok(branch));
+            }
+        }
+    }
+
+    #[test]
+    fn every_truncation_is_an_error() {
+        let bytes = encode_branch(&branch());
+        for len in 0..bytes.len() {
+            assert!(
+                matches!(
+                    decode_branch(&bytes[..len]),
+                    Err(BranchCodecError::Truncated { actual, .. }) if actual == len
+                ),
+                "{} bytes",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn corrupted_branches_are_errors() {
+        let bytes = encode_branch(&branch());
+
+        let mut version = bytes.clone();
+        version[0] = BRANCH_CODEC_VERSION + 1;
+        assert_eq!(
+            decode_branch(&version),
+            Err(BranchCodecError::UnsupportedVersion(
+                BRANCH_CODEC_VERSION + 1
+            ))
+        );
+
+        let mut tag = bytes.clone();
+        tag[34] = 0xff;
+        assert_eq!(
+            decode_branch(&tag),
+            Err(BranchCodecError::UnknownTag {
+                tag: 0xff,
+                offset: 34
+            })
+        );
+
+        let mut trailing = bytes;
+        trailing.push(0);
+        assert_eq!(
+            decode_branch(&trailing),
+            Err(BranchCodecError::TrailingBytes(1))
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn random_branches_round_trip(branch in branch_node()) {
+            assert_eq!(decode_branch(&encode_branch(&branch)), Ok(branch));
+        }
+    }
+}
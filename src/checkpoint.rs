@@ -0,0 +1,207 @@
+//! Checkpoints a store can be rolled back to, to revert speculative blocks
+
+use std::any::Any;
+use std::cell::{Ref, RefCell, RefMut};
+use std::rc::Rc;
+
+use lsmtree::bytes::Bytes;
+use monotree::Monotree;
+use sparse_merkle_tree::{
+    traits::{StoreReadOps, StoreWriteOps},
+    BranchKey, BranchNode, H256,
+};
+
+use crate::error::StoreError;
+
+thread_local! {
+    // Store the next monotree created by `open_monotree` takes as its database
+    static TO_OPEN: RefCell<Option<Box<dyn Any>>> = const { RefCell::new(None) };
+}
+
+/// Store that can be brought back to the content it had when a checkpoint was taken
+///
+/// Rolling back to a checkpoint or releasing it also drops the checkpoints taken after it.
+pub trait CheckpointStore {
+    type Checkpoint;
+
+    /// Saves the content of the store, the writes made after it can be undone
+    fn checkpoint(&mut self) -> Result<Self::Checkpoint, StoreError>;
+
+    /// Undoes the writes made since `checkpoint`
+    fn rollback(&mut self, checkpoint: Self::Checkpoint) -> Result<(), StoreError>;
+
+    /// Keeps the writes made since `checkpoint`, there is no going back to it
+    fn release(&mut self, checkpoint: Self::Checkpoint) -> Result<(), StoreError>;
+}
+
+/// Ids of the checkpoints held, oldest first, for the stores that do not log their changes
+#[derive(Debug, Default)]
+pub struct HeldCheckpoints {
+    ids: Vec<u64>,
+    next_id: u64,
+}
+
+impl HeldCheckpoints {
+    /// Id of a new checkpoint, never given twice
+    pub fn take(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.ids.push(id);
+        id
+    }
+
+    /// Drops `id` and the checkpoints taken after it, `InvalidCheckpoint` if it is not held
+    pub fn drop_from(&mut self, id: u64) -> Result<(), StoreError> {
+        let position = self
+            .ids
+            .iter()
+            .position(|held| *held == id)
+            .ok_or(StoreError::InvalidCheckpoint)?;
+        self.ids.truncate(position);
+        Ok(())
+    }
+}
+
+/// Store owned by a tree, with a handle on it kept by the backend to roll it back
+///
+/// A clone is a copy of the store, `handle` gives another handle on the same one.
+pub struct SharedStore<S>(Rc<RefCell<S>>);
+
+impl<S> SharedStore<S> {
+    pub fn new(store: S) -> Self {
+        SharedStore(Rc::new(RefCell::new(store)))
+    }
+
+    pub fn handle(&self) -> Self {
+        SharedStore(self.0.clone())
+    }
+
+    pub fn borrow(&self) -> Ref<'_, S> {
+        self.0.borrow()
+    }
+
+    pub fn borrow_mut(&self) -> RefMut<'_, S> {
+        self.0.borrow_mut()
+    }
+}
+
+impl<S: Default> Default for SharedStore<S> {
+    fn default() -> Self {
+        SharedStore::new(S::default())
+    }
+}
+
+impl<S: Clone> Clone for SharedStore<S> {
+    fn clone(&self) -> Self {
+        SharedStore::new(self.borrow().clone())
+    }
+}
+
+// SPARSE MERKLE TREE
+
+impl<S: StoreReadOps<H256>> StoreReadOps<H256> for SharedStore<S> {
+    fn get_branch(
+        &self,
+        branch_key: &BranchKey,
+    ) -> Result<Option<BranchNode>, sparse_merkle_tree::error::Error> {
+        self.borrow().get_branch(branch_key)
+    }
+
+    fn get_leaf(&self, leaf_key: &H256) -> Result<Option<H256>, sparse_merkle_tree::error::Error> {
+        self.borrow().get_leaf(leaf_key)
+    }
+}
+
+impl<S: StoreWriteOps<H256>> StoreWriteOps<H256> for SharedStore<S> {
+    fn insert_branch(
+        &mut self,
+        node_key: BranchKey,
+        branch: BranchNode,
+    ) -> Result<(), sparse_merkle_tree::error::Error> {
+        self.borrow_mut().insert_branch(node_key, branch)
+    }
+
+    fn insert_leaf(
+        &mut self,
+        leaf_key: H256,
+        leaf: H256,
+    ) -> Result<(), sparse_merkle_tree::error::Error> {
+        self.borrow_mut().insert_leaf(leaf_key, leaf)
+    }
+
+    fn remove_branch(
+        &mut self,
+        node_key: &BranchKey,
+    ) -> Result<(), sparse_merkle_tree::error::Error> {
+        self.borrow_mut().remove_branch(node_key)
+    }
+
+    fn remove_leaf(&mut self, leaf_key: &H256) -> Result<(), sparse_merkle_tree::error::Error> {
+        self.borrow_mut().remove_leaf(leaf_key)
+    }
+}
+
+// LSMTREE
+
+impl<S: lsmtree::KVStore> lsmtree::KVStore for SharedStore<S> {
+    type Hasher = S::Hasher;
+    type Error = S::Error;
+
+    fn get(&self, key: &[u8]) -> Result<Option<Bytes>, Self::Error> {
+        self.borrow().get(key)
+    }
+
+    fn set(&mut self, key: Bytes, value: Bytes) -> Result<(), Self::Error> {
+        self.borrow_mut().set(key, value)
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Result<Bytes, Self::Error> {
+        self.borrow_mut().remove(key)
+    }
+
+    fn contains(&self, key: &[u8]) -> Result<bool, Self::Error> {
+        self.borrow().contains(key)
+    }
+}
+
+// MONOTREE
+
+/// Monotree over `store`, monotree opens its database itself from a path and gives no access to it
+pub fn open_monotree<S, H>(store: &SharedStore<S>) -> Monotree<SharedStore<S>, H>
+where
+    S: monotree::Database + 'static,
+    H: monotree::Hasher,
+{
+    TO_OPEN.with(|to_open| to_open.replace(Some(Box::new(store.handle()))));
+    Monotree::new("")
+}
+
+impl<S: monotree::Database + 'static> monotree::Database for SharedStore<S> {
+    fn new(_dbpath: &str) -> Self {
+        let store = TO_OPEN
+            .with(|to_open| to_open.take())
+            .and_then(|store| store.downcast::<SharedStore<S>>().ok())
+            .expect("a monotree over a shared store is created by `open_monotree`");
+        *store
+    }
+
+    fn get(&mut self, key: &[u8]) -> monotree::Result<Option<Vec<u8>>> {
+        self.borrow_mut().get(key)
+    }
+
+    fn put(&mut self, key: &[u8], value: Vec<u8>) -> monotree::Result<()> {
+        self.borrow_mut().put(key, value)
+    }
+
+    fn delete(&mut self, key: &[u8]) -> monotree::Result<()> {
+        self.borrow_mut().delete(key)
+    }
+
+    fn init_batch(&mut self) -> monotree::Result<()> {
+        self.borrow_mut().init_batch()
+    }
+
+    fn finish_batch(&mut self) -> monotree::Result<()> {
+        self.borrow_mut().finish_batch()
+    }
+}
@@ -0,0 +1,255 @@
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use lsmtree::bytes::Bytes;
+use sparse_merkle_tree::{
+    traits::{StoreReadOps, StoreWriteOps},
+    BranchKey, BranchNode, H256,
+};
+
+use crate::branch_codec::{encoded_branch_len, BRANCH_KEY_LEN};
+use crate::checkpoint::CheckpointStore;
+use crate::error::StoreError;
+use crate::metrics::StoreMetrics;
+
+/// Store forwarding every call to `inner`, and counting it in the metrics being recorded
+///
+/// Tells apart the time a tree spends hashing from the time it spends in its store,
+/// without any change to the tree libraries.
+pub struct CountingStore<S> {
+    inner: S,
+    metrics: Rc<StoreMetrics>,
+}
+
+impl<S> CountingStore<S> {
+    pub fn new(inner: S) -> Self {
+        CountingStore {
+            inner,
+            metrics: StoreMetrics::current(),
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S: Default> Default for CountingStore<S> {
+    fn default() -> Self {
+        CountingStore::new(S::default())
+    }
+}
+
+impl<S: Clone> Clone for CountingStore<S> {
+    fn clone(&self) -> Self {
+        CountingStore::new(self.inner.clone())
+    }
+}
+
+// The call is only timed when the latencies are traced, `Instant::now` is not free
+fn timed<T>(metrics: &StoreMetrics, call: impl FnOnce() -> T) -> (T, Option<Duration>) {
+    if !metrics.traces_latency() {
+        return (call(), None);
+    }
+    let start = Instant::now();
+    let result = call();
+    (result, Some(start.elapsed()))
+}
+
+fn found_len<V: AsRef<[u8]>, E>(found: &Result<Option<V>, E>) -> usize {
+    match found {
+        Ok(Some(value)) => value.as_ref().len(),
+        _ => 0,
+    }
+}
+
+impl<S: CheckpointStore> CheckpointStore for CountingStore<S> {
+    type Checkpoint = S::Checkpoint;
+
+    fn checkpoint(&mut self) -> Result<S::Checkpoint, StoreError> {
+        let (checkpoint, latency) = timed(&self.metrics, || self.inner.checkpoint());
+        self.metrics.add_call("checkpoint", 0, 0, latency);
+        checkpoint
+    }
+
+    fn rollback(&mut self, checkpoint: S::Checkpoint) -> Result<(), StoreError> {
+        let (result, latency) = timed(&self.metrics, || self.inner.rollback(checkpoint));
+        self.metrics.add_call("rollback", 0, 0, latency);
+        result
+    }
+
+    fn release(&mut self, checkpoint: S::Checkpoint) -> Result<(), StoreError> {
+        let (result, latency) = timed(&self.metrics, || self.inner.release(checkpoint));
+        self.metrics.add_call("release", 0, 0, latency);
+        result
+    }
+}
+
+// MONOTREE
+
+impl<S: monotree::Database> monotree::Database for CountingStore<S> {
+    fn new(dbpath: &str) -> Self {
+        CountingStore::new(S::new(dbpath))
+    }
+
+    fn get(&mut self, key: &[u8]) -> monotree::Result<Option<Vec<u8>>> {
+        let (found, latency) = timed(&self.metrics, || self.inner.get(key));
+        self.metrics
+            .add_call("get", key.len(), found_len(&found), latency);
+        found
+    }
+
+    fn put(&mut self, key: &[u8], value: Vec<u8>) -> monotree::Result<()> {
+        let value_len = value.len();
+        let (result, latency) = timed(&self.metrics, || self.inner.put(key, value));
+        self.metrics.add_call("put", key.len(), value_len, latency);
+        result
+    }
+
+    fn delete(&mut self, key: &[u8]) -> monotree::Result<()> {
+        let (result, latency) = timed(&self.metrics, || self.inner.delete(key));
+        self.metrics.add_call("delete", key.len(), 0, latency);
+        result
+    }
+
+    fn init_batch(&mut self) -> monotree::Result<()> {
+        let (result, latency) = timed(&self.metrics, || self.inner.init_batch());
+        self.metrics.add_call("init_batch", 0, 0, latency);
+        result
+    }
+
+    fn finish_batch(&mut self) -> monotree::Result<()> {
+        let (result, latency) = timed(&self.metrics, || self.inner.finish_batch());
+        self.metrics.add_call("finish_batch", 0, 0, latency);
+        result
+    }
+}
+
+// SPARSE MERKLE TREE
+
+impl<S: StoreReadOps<H256>> StoreReadOps<H256> for CountingStore<S> {
+    fn get_branch(
+        &self,
+        branch_key: &BranchKey,
+    ) -> Result<Option<BranchNode>, sparse_merkle_tree::error::Error> {
+        let (found, latency) = timed(&self.metrics, || self.inner.get_branch(branch_key));
+        let value_len = match &found {
+            Ok(Some(branch)) => encoded_branch_len(branch),
+            _ => 0,
+        };
+        self.metrics
+            .add_call("get_branch", BRANCH_KEY_LEN, value_len, latency);
+        found
+    }
+
+    fn get_leaf(&self, leaf_key: &H256) -> Result<Option<H256>, sparse_merkle_tree::error::Error> {
+        let (found, latency) = timed(&self.metrics, || self.inner.get_leaf(leaf_key));
+        let value_len = match &found {
+            Ok(Some(_)) => 32,
+            _ => 0,
+        };
+        self.metrics.add_call("get_leaf", 32, value_len, latency);
+        found
+    }
+}
+
+impl<S: StoreWriteOps<H256>> StoreWriteOps<H256> for CountingStore<S> {
+    fn insert_branch(
+        &mut self,
+        node_key: BranchKey,
+        branch: BranchNode,
+    ) -> Result<(), sparse_merkle_tree::error::Error> {
+        let value_len = encoded_branch_len(&branch);
+        let (result, latency) = timed(&self.metrics, || self.inner.insert_branch(node_key, branch));
+        self.metrics
+            .add_call("insert_branch", BRANCH_KEY_LEN, value_len, latency);
+        result
+    }
+
+    fn insert_leaf(
+        &mut self,
+        leaf_key: H256,
+        leaf: H256,
+    ) -> Result<(), sparse_merkle_tree::error::Error> {
+        let (result, latency) = timed(&self.metrics, || self.inner.insert_leaf(leaf_key, leaf));
+        self.metrics.add_call("insert_leaf", 32, 32, latency);
+        result
+    }
+
+    fn remove_branch(
+        &mut self,
+        node_key: &BranchKey,
+    ) -> Result<(), sparse_merkle_tree::error::Error> {
+        let (result, latency) = timed(&self.metrics, || self.inner.remove_branch(node_key));
+        self.metrics
+            .add_call("remove_branch", BRANCH_KEY_LEN, 0, latency);
+        result
+    }
+
+    fn remove_leaf(&mut self, leaf_key: &H256) -> Result<(), sparse_merkle_tree::error::Error> {
+        let (result, latency) = timed(&self.metrics, || self.inner.remove_leaf(leaf_key));
+        self.metrics.add_call("remove_leaf", 32, 0, latency);
+        result
+    }
+}
+
+// LSMTREE
+
+impl<S: lsmtree::KVStore> lsmtree::KVStore for CountingStore<S> {
+    type Hasher = S::Hasher;
+    type Error = S::Error;
+
+    fn get(&self, key: &[u8]) -> Result<Option<Bytes>, Self::Error> {
+        let (found, latency) = timed(&self.metrics, || self.inner.get(key));
+        self.metrics
+            .add_call("get", key.len(), found_len(&found), latency);
+        found
+    }
+
+    fn set(&mut self, key: Bytes, value: Bytes) -> Result<(), Self::Error> {
+        let (key_len, value_len) = (key.len(), value.len());
+        let (result, latency) = timed(&self.metrics, || self.inner.set(key, value));
+        self.metrics.add_call("set", key_len, value_len, latency);
+        result
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Result<Bytes, Self::Error> {
+        let (removed, latency) = timed(&self.metrics, || self.inner.remove(key));
+        let value_len = removed.as_ref().map_or(0, |value| value.len());
+        self.metrics
+            .add_call("remove", key.len(), value_len, latency);
+        removed
+    }
+
+    fn contains(&self, key: &[u8]) -> Result<bool, Self::Error> {
+        let (result, latency) = timed(&self.metrics, || self.inner.contains(key));
+        self.metrics.add_call("contains", key.len(), 0, latency);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use monotree::Database;
+
+    use super::CountingStore;
+    use crate::memory_store::MemoryStore;
+    use crate::metrics::StoreMetrics;
+
+    #[test]
+    fn calls_are_counted_and_timed_by_method() {
+        let (mut store, metrics) =
+            StoreMetrics::record_latencies(CountingStore::<MemoryStore>::default);
+        store.put(&[1; 32], vec![2; 10]).unwrap();
+        store.put(&[2; 32], vec![2; 20]).unwrap();
+        assert_eq!(store.get(&[1; 32]).unwrap(), Some(vec![2; 10]));
+        store.get(&[3; 32]).unwrap();
+
+        let calls = metrics.calls();
+        let (put, get) = (&calls["put"], &calls["get"]);
+        assert_eq!((put.calls, put.key_bytes, put.value_bytes), (2, 64, 30));
+        assert_eq!((get.calls, get.key_bytes, get.value_bytes), (2, 64, 10));
+        assert_eq!(get.latency_histogram.iter().sum::<u64>(), 2);
+        assert!(get.latency_quantile(0.5).unwrap() <= get.latency_quantile(1.0).unwrap());
+    }
+}
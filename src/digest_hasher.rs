@@ -0,0 +1,146 @@
+use lsmtree::digest::typenum::U32;
+use lsmtree::digest::{FixedOutput, HashMarker, Output, OutputSizeUser, Update};
+
+use crate::hasher::SmtHasher;
+
+pub type Sha256SmtHasher = DigestSmtHasher<sha2::Sha256>;
+pub type Keccak256SmtHasher = DigestSmtHasher<sha3::Keccak256>;
+pub type Blake2bSmtHasher = DigestSmtHasher<blake2::Blake2b<U32>>;
+
+/// Wraps any 32 bytes RustCrypto hash function
+#[derive(Debug, Default, Clone)]
+pub struct DigestSmtHasher<D>(D);
+
+impl SmtHasher for Sha256SmtHasher {
+    const NAME: &'static str = "sha256";
+}
+
+impl SmtHasher for Keccak256SmtHasher {
+    const NAME: &'static str = "keccak256";
+}
+
+impl SmtHasher for Blake2bSmtHasher {
+    const NAME: &'static str = "blake2b";
+}
+
+// MONOTREE
+
+impl<D> monotree::hasher::Hasher for DigestSmtHasher<D>
+where
+    D: Update + FixedOutput + OutputSizeUser<OutputSize = U32> + Default,
+{
+    fn new() -> Self {
+        DigestSmtHasher(D::default())
+    }
+
+    fn digest(&self, bytes: &[u8]) -> monotree::Hash {
+        let mut hasher = D::default();
+        hasher.update(bytes);
+        hasher.finalize_fixed().into()
+    }
+}
+
+// SPARSE MERKLE TREE
+
+impl<D> sparse_merkle_tree::traits::Hasher for DigestSmtHasher<D>
+where
+    D: Update + FixedOutput + OutputSizeUser<OutputSize = U32>,
+{
+    fn write_h256(&mut self, h: &sparse_merkle_tree::H256) {
+        self.0.update(h.as_slice());
+    }
+
+    fn write_byte(&mut self, b: u8) {
+        self.0.update(&[b][..]);
+    }
+
+    fn finish(self) -> sparse_merkle_tree::H256 {
+        let hash: [u8; 32] = self.0.finalize_fixed().into();
+        hash.into()
+    }
+}
+
+// LSMTREE, `Digest` comes from the blanket implementation over these traits
+
+impl<D> OutputSizeUser for DigestSmtHasher<D> {
+    type OutputSize = U32;
+}
+
+impl<D: Update> Update for DigestSmtHasher<D> {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+}
+
+impl<D> FixedOutput for DigestSmtHasher<D>
+where
+    D: FixedOutput + OutputSizeUser<OutputSize = U32>,
+{
+    fn finalize_into(self, out: &mut Output<Self>) {
+        self.0.finalize_into(out);
+    }
+}
+
+impl<D> HashMarker for DigestSmtHasher<D> {}
+
+#[cfg(test)]
+mod tests {
+    use super::{Blake2bSmtHasher, Keccak256SmtHasher, Sha256SmtHasher};
+    use crate::hasher::{adapter_digests, SmtHasher};
+
+    // FIPS 180-2 examples for SHA-256, the Keccak team reference outputs for Keccak-256
+    // and RFC 7693 BLAKE2b with a 32 bytes output
+    #[rustfmt::skip]
+    const TEST_VECTORS: [(&[u8], &str, &str, &str); 3] = [
+        (
+            b"",
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470",
+            "0e5751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a8",
+        ),
+        (
+            b"abc",
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            "4e03657aea45a94fc7d47ba826c8d667c0d1e6e33a64a036ec44f58fa12d6c45",
+            "bddd813c634239723171ef3fee98579b94964e3bb1cb3e427262c8c068d52319",
+        ),
+        (
+            b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+            "45d3b367a6904e6e8d502ee04999a7c27647f91fa845d456525fd352ae3d7371",
+            "5f7a93da9c5621583f22e49e8e91a40cbba37536622235a380f434b9f68e49c4",
+        ),
+    ];
+
+    fn to_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn check_vector<H: SmtHasher>(input: &[u8], expected: &str) {
+        assert_eq!(
+            to_hex(&adapter_digests::<H>(input)),
+            expected,
+            "{} of {:?}",
+            H::NAME,
+            String::from_utf8_lossy(input)
+        );
+    }
+
+    #[test]
+    fn published_test_vectors() {
+        for (input, sha256, keccak256, blake2b) in TEST_VECTORS {
+            check_vector::<Sha256SmtHasher>(input, sha256);
+            check_vector::<Keccak256SmtHasher>(input, keccak256);
+            check_vector::<Blake2bSmtHasher>(input, blake2b);
+        }
+    }
+
+    // A sparse-merkle-tree branch is written as a height byte then two hashes
+    #[test]
+    fn branch_input_gives_the_same_digest_in_every_library() {
+        let input = [&[7][..], &[0x11; 32][..], &[0x22; 32][..]].concat();
+        adapter_digests::<Sha256SmtHasher>(&input);
+        adapter_digests::<Keccak256SmtHasher>(&input);
+        adapter_digests::<Blake2bSmtHasher>(&input);
+    }
+}
@@ -0,0 +1,99 @@
+use lsmtree::BadProof;
+
+/// Errors raised by the storage backends
+#[derive(Debug)]
+pub enum StoreError {
+    Io(std::io::Error),
+    Corrupted(String),
+    BadKeyLength {
+        expected: usize,
+        actual: usize,
+    },
+    MissingKey(Vec<u8>),
+    /// The checkpoint was rolled back to or released already
+    InvalidCheckpoint,
+    /// Checkpoints are taken and rolled back to between batches only
+    PendingBatch,
+    /// Required by lsmtree, that builds store errors out of invalid proofs
+    BadProof,
+}
+
+impl std::fmt::Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StoreError::Io(err) => write!(f, "Storage I/O error: {}", err),
+            StoreError::Corrupted(details) => write!(f, "Corrupted store: {}", details),
+            StoreError::BadKeyLength { expected, actual } => write!(
+                f,
+                "Bad key length, expected {} bytes actual {}",
+                expected, actual
+            ),
+            StoreError::MissingKey(key) => write!(f, "Missing key {:?}", key),
+            StoreError::InvalidCheckpoint => {
+                write!(f, "Checkpoint already rolled back or released")
+            }
+            StoreError::PendingBatch => write!(f, "Checkpoint or rollback during a batch"),
+            StoreError::BadProof => write!(f, "Bad proof"),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StoreError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for StoreError {
+    fn from(err: std::io::Error) -> Self {
+        StoreError::Io(err)
+    }
+}
+
+impl From<rocksdb::Error> for StoreError {
+    fn from(err: rocksdb::Error) -> Self {
+        match err.kind() {
+            rocksdb::ErrorKind::Corruption => StoreError::Corrupted(err.into_string()),
+            _ => StoreError::Io(std::io::Error::other(err)),
+        }
+    }
+}
+
+impl From<BadProof> for StoreError {
+    fn from(_: BadProof) -> Self {
+        StoreError::BadProof
+    }
+}
+
+impl From<StoreError> for monotree::Errors {
+    fn from(err: StoreError) -> Self {
+        monotree::Errors::new(&err.to_string())
+    }
+}
+
+impl From<StoreError> for sparse_merkle_tree::error::Error {
+    fn from(err: StoreError) -> Self {
+        sparse_merkle_tree::error::Error::Store(err.to_string())
+    }
+}
+
+/// Keys of the in-memory store are 32 bytes hashes
+pub fn key_from_slice(bytes: &[u8]) -> Result<[u8; 32], StoreError> {
+    bytes.try_into().map_err(|_| StoreError::BadKeyLength {
+        expected: 32,
+        actual: bytes.len(),
+    })
+}
+
+/// A stored value that should be a 32 bytes hash
+pub fn stored_hash(bytes: &[u8]) -> Result<[u8; 32], StoreError> {
+    bytes.try_into().map_err(|_| {
+        StoreError::Corrupted(format!(
+            "expected a 32 bytes hash, found {} bytes",
+            bytes.len()
+        ))
+    })
+}
@@ -0,0 +1,34 @@
+/// Hash function usable by all the benchmarked libraries
+pub trait SmtHasher:
+    monotree::hasher::Hasher
+    + sparse_merkle_tree::traits::Hasher
+    + lsmtree::digest::Digest
+    + Default
+    + Clone
+    + 'static
+{
+    /// Name of the hash function in the benchmark labels and database paths
+    const NAME: &'static str;
+}
+
+/// Digest of `input` through the monotree, sparse-merkle-tree and lsmtree traits, which have to agree
+#[cfg(test)]
+pub(crate) fn adapter_digests<H: SmtHasher>(input: &[u8]) -> [u8; 32] {
+    let monotree = monotree::Hasher::digest(&<H as monotree::Hasher>::new(), input);
+    let mut hasher = H::default();
+    for b in input {
+        hasher.write_byte(*b);
+    }
+    let smt: [u8; 32] = hasher.finish().into();
+    let lsmtree = <H as lsmtree::digest::Digest>::digest(input);
+    assert_eq!(
+        monotree, smt,
+        "monotree and sparse-merkle-tree digests differ"
+    );
+    assert_eq!(
+        monotree[..],
+        lsmtree[..],
+        "monotree and lsmtree digests differ"
+    );
+    monotree
+}
@@ -2,6 +2,24 @@ pub mod lsmtree;
 pub mod monotree;
 pub mod sparse_merkle_tree;
 
+pub mod backend;
+pub mod bench_dir;
 pub mod blake3;
+pub mod branch_codec;
+pub mod checkpoint;
+pub mod counting_store;
+pub mod digest_hasher;
+pub mod error;
+pub mod hasher;
 pub mod memory_store;
+pub mod metrics;
+pub mod poseidon;
+pub mod profile;
 pub mod rocksdb;
+pub mod rocksdb_cf;
+pub mod rocksdb_config;
+#[cfg(test)]
+mod store_model;
+pub mod suite;
+pub mod versioned_monotree;
+pub mod workload;
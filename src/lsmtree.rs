@@ -1,86 +1,430 @@
-use criterion::{Bencher, Criterion};
-use lsmtree::{bytes::Bytes, KVStore, SparseMerkleTree};
+use criterion::measurement::WallTime;
+use criterion::{Bencher, BenchmarkGroup, Criterion};
+use lsmtree::{
+    bytes::Bytes, KVStore, SparseCompactMerkleProof, SparseMerkleProof, SparseMerkleTree,
+};
+use monotree::{utils::slice_to_hash, Hash};
 
-use crate::{memory_store::MemoryStore, rocksdb::SmtRockSdb};
-
-fn random_hash() -> [u8; 32] {
-    monotree::utils::random_hash()
-}
+use crate::backend::SmtBackend;
+use crate::blake3::Blake3SmtHasher;
+use crate::checkpoint::{CheckpointStore, SharedStore};
+use crate::counting_store::CountingStore;
+use crate::digest_hasher::{Blake2bSmtHasher, Keccak256SmtHasher, Sha256SmtHasher};
+use crate::error::StoreError;
+use crate::hasher::SmtHasher;
+use crate::poseidon::PoseidonSmtHasher;
+use crate::profile::WorkloadProfile;
+use crate::rocksdb_config::rocksdb_label;
+use crate::suite::{
+    bench_backend, bench_backend_proofs, bench_blocks, bench_checkpoints, bench_profiles,
+    bench_tree_sizes, filled_backend, report_footprint, report_proof_size, report_store_calls,
+};
+use crate::workload::Workload;
+use crate::{memory_store::MemoryStore, rocksdb::SmtRockSdb, rocksdb_cf::SmtRockSdbCf};
 
 pub fn add_lsmtree_benches(c: &mut Criterion, sample_size: usize, tree_size: usize) {
+    report_footprint(
+        "lsmtree/memstore+blake3",
+        init_lsmtree_memstore::<Blake3SmtHasher>,
+        tree_size,
+    );
+    report_footprint(
+        "lsmtree/rocksdb+blake3",
+        init_lsmtree_rocksdb::<Blake3SmtHasher>,
+        tree_size,
+    );
+    report_store_calls(
+        "lsmtree/memstore+blake3",
+        init_lsmtree_counted_memstore::<Blake3SmtHasher>,
+        tree_size,
+    );
+    report_store_calls(
+        "lsmtree/rocksdb+blake3",
+        init_lsmtree_counted_rocksdb::<Blake3SmtHasher>,
+        tree_size,
+    );
+
     let mut group = c.benchmark_group("lsmtree");
     group.sample_size(sample_size);
 
-    group.bench_function("memstore+blake3", |b| {
-        test_tree(init_lsmtree_memstore_blake3(), b, tree_size)
-    });
+    bench_lsmtree_hasher::<Blake3SmtHasher>(&mut group, tree_size);
+    bench_lsmtree_hasher::<Sha256SmtHasher>(&mut group, tree_size);
+    bench_lsmtree_hasher::<Keccak256SmtHasher>(&mut group, tree_size);
+    bench_lsmtree_hasher::<Blake2bSmtHasher>(&mut group, tree_size);
+    bench_lsmtree_hasher::<PoseidonSmtHasher>(&mut group, tree_size);
+}
 
-    group.bench_function("memstore+blake3/read", |b| {
-        test_read_only(init_lsmtree_memstore_blake3(), b, tree_size)
-    });
+fn bench_lsmtree_hasher<H: SmtHasher>(group: &mut BenchmarkGroup<WallTime>, tree_size: usize) {
+    let label = format!("memstore+{}", H::NAME);
+    bench_backend(group, &label, init_lsmtree_memstore::<H>, tree_size);
+    let label = format!("{}+{}", rocksdb_label("rocksdb"), H::NAME);
+    bench_backend(group, &label, init_lsmtree_rocksdb::<H>, tree_size);
+}
 
-    group.bench_function("memstore+blake3/write", |b| {
-        test_write_only(init_lsmtree_memstore_blake3(), b, tree_size)
-    });
+pub fn add_lsmtree_proof_benches(c: &mut Criterion, sample_size: usize, tree_size: usize) {
+    let rocksdb = format!("{}+blake3", rocksdb_label("rocksdb"));
+    report_proof_size(
+        "lsmtree/proof",
+        "memstore+blake3",
+        init_lsmtree_memstore::<Blake3SmtHasher>,
+        tree_size,
+    );
+    report_proof_size(
+        "lsmtree/proof",
+        &rocksdb,
+        init_lsmtree_rocksdb::<Blake3SmtHasher>,
+        tree_size,
+    );
+    report_compact_proof_size(
+        "memstore+blake3",
+        init_lsmtree_memstore::<Blake3SmtHasher>,
+        tree_size,
+    );
+    report_compact_proof_size(&rocksdb, init_lsmtree_rocksdb::<Blake3SmtHasher>, tree_size);
 
-    group.bench_function("rocksdb+blake3", |b| {
-        test_tree(init_lsmtree_rocksdb_blake3(), b, tree_size)
-    });
+    let mut group = c.benchmark_group("lsmtree/proof");
+    group.sample_size(sample_size);
 
-    group.bench_function("rocksdb+blake3/read", |b| {
-        test_read_only(init_lsmtree_rocksdb_blake3(), b, tree_size)
+    bench_lsmtree_proof_hasher::<Blake3SmtHasher>(&mut group, tree_size);
+    bench_lsmtree_proof_hasher::<Sha256SmtHasher>(&mut group, tree_size);
+    bench_lsmtree_proof_hasher::<Keccak256SmtHasher>(&mut group, tree_size);
+    bench_lsmtree_proof_hasher::<Blake2bSmtHasher>(&mut group, tree_size);
+    bench_lsmtree_proof_hasher::<PoseidonSmtHasher>(&mut group, tree_size);
+}
+
+fn bench_lsmtree_proof_hasher<H: SmtHasher>(
+    group: &mut BenchmarkGroup<WallTime>,
+    tree_size: usize,
+) {
+    let label = format!("memstore+{}", H::NAME);
+    bench_backend_proofs(group, &label, init_lsmtree_memstore::<H>, tree_size);
+    group.bench_function(format!("{}/compact", label), |b| {
+        test_proof_compaction(
+            filled_backend(&label, &init_lsmtree_memstore::<H>, tree_size),
+            b,
+        )
     });
 
-    group.bench_function("rocksdb+blake3/write", |b| {
-        test_write_only(init_lsmtree_rocksdb_blake3(), b, tree_size)
+    let label = format!("{}+{}", rocksdb_label("rocksdb"), H::NAME);
+    bench_backend_proofs(group, &label, init_lsmtree_rocksdb::<H>, tree_size);
+    group.bench_function(format!("{}/compact", label), |b| {
+        test_proof_compaction(
+            filled_backend(&label, &init_lsmtree_rocksdb::<H>, tree_size),
+            b,
+        )
     });
 }
 
-fn fill_lsmtree<S: KVStore>(tree: &mut SparseMerkleTree<S>, nb: usize) {
-    for _ in 0..nb {
-        let key = random_hash();
-        let leaf = random_hash();
-        tree.update(key.as_slice(), Bytes::from(leaf.to_vec()))
-            .unwrap();
+pub fn add_lsmtree_layout_benches(c: &mut Criterion, sample_size: usize, tree_size: usize) {
+    let mut group = c.benchmark_group("lsmtree/rocksdb-layout");
+    group.sample_size(sample_size);
+
+    bench_backend(
+        &mut group,
+        &format!("{}+blake3", rocksdb_label("two-db")),
+        init_lsmtree_rocksdb::<Blake3SmtHasher>,
+        tree_size,
+    );
+    bench_backend(
+        &mut group,
+        &format!("{}+blake3", rocksdb_label("column-families")),
+        init_lsmtree_rocksdb_cf::<Blake3SmtHasher>,
+        tree_size,
+    );
+}
+
+pub fn add_lsmtree_block_benches(
+    c: &mut Criterion,
+    sample_size: usize,
+    tree_size: usize,
+    block_sizes: &[usize],
+) {
+    let mut group = c.benchmark_group("lsmtree/blocks");
+    group.sample_size(sample_size);
+
+    let init = init_lsmtree_memstore::<Blake3SmtHasher>;
+    bench_blocks(&mut group, "memstore+blake3", init, tree_size, block_sizes);
+    let label = format!("{}+blake3", rocksdb_label("rocksdb"));
+    bench_blocks(
+        &mut group,
+        &label,
+        init_lsmtree_rocksdb::<Blake3SmtHasher>,
+        tree_size,
+        block_sizes,
+    );
+}
+
+// Speculative blocks reverted, both stores undo the writes made since the checkpoint
+pub fn add_lsmtree_checkpoint_benches(
+    c: &mut Criterion,
+    sample_size: usize,
+    tree_size: usize,
+    update_counts: &[usize],
+) {
+    let mut group = c.benchmark_group("lsmtree/checkpoints");
+    group.sample_size(sample_size);
+
+    let init = init_lsmtree_memstore::<Blake3SmtHasher>;
+    bench_checkpoints(
+        &mut group,
+        "memstore+blake3",
+        init,
+        tree_size,
+        update_counts,
+    );
+    let label = format!("{}+blake3", rocksdb_label("rocksdb"));
+    bench_checkpoints(
+        &mut group,
+        &label,
+        init_lsmtree_rocksdb::<Blake3SmtHasher>,
+        tree_size,
+        update_counts,
+    );
+}
+
+pub fn add_lsmtree_profile_benches(c: &mut Criterion, sample_size: usize, tree_size: usize) {
+    let mut group = c.benchmark_group("lsmtree/profiles");
+    group.sample_size(sample_size);
+
+    let profiles = WorkloadProfile::all();
+    let init = init_lsmtree_memstore::<Blake3SmtHasher>;
+    bench_profiles(&mut group, "memstore+blake3", init, tree_size, &profiles);
+    let label = format!("{}+blake3", rocksdb_label("rocksdb"));
+    bench_profiles(
+        &mut group,
+        &label,
+        init_lsmtree_rocksdb::<Blake3SmtHasher>,
+        tree_size,
+        &profiles,
+    );
+}
+
+pub fn add_lsmtree_tree_size_benches(c: &mut Criterion, sample_size: usize, tree_sizes: &[usize]) {
+    let mut group = c.benchmark_group("lsmtree/tree-size");
+    group.sample_size(sample_size);
+
+    bench_tree_sizes(
+        &mut group,
+        "memstore+blake3",
+        init_lsmtree_memstore::<Blake3SmtHasher>,
+        tree_sizes,
+    );
+    bench_tree_sizes(
+        &mut group,
+        &format!("{}+blake3", rocksdb_label("rocksdb")),
+        init_lsmtree_rocksdb::<Blake3SmtHasher>,
+        tree_sizes,
+    );
+}
+
+/// The tree owns its stores, the backend rolls them back through other handles on them
+pub struct LsmtreeBackend<S: KVStore> {
+    tree: SparseMerkleTree<SharedStore<S>>,
+    nodes: SharedStore<S>,
+    values: SharedStore<S>,
+}
+
+impl<S: KVStore> LsmtreeBackend<S> {
+    fn import(nodes: SharedStore<S>, values: SharedStore<S>, root: Bytes) -> Self {
+        LsmtreeBackend {
+            tree: SparseMerkleTree::import(nodes.handle(), values.handle(), root),
+            nodes,
+            values,
+        }
+    }
+}
+
+impl<S: KVStore + Default> Default for LsmtreeBackend<S> {
+    fn default() -> Self {
+        let (nodes, values) = (SharedStore::default(), SharedStore::default());
+        LsmtreeBackend {
+            tree: SparseMerkleTree::new_with_stores(nodes.handle(), values.handle()),
+            nodes,
+            values,
+        }
+    }
+}
+
+impl<S> SmtBackend for LsmtreeBackend<S>
+where
+    S: KVStore + CheckpointStore + Clone,
+    S::Hasher: Clone,
+    S::Error: From<StoreError>,
+{
+    type Proof = SparseMerkleProof<S::Hasher>;
+    type Error = S::Error;
+    // Updates delete the replaced nodes, both stores are rolled back with the root
+    type Checkpoint = (Bytes, S::Checkpoint, S::Checkpoint);
+
+    // See `verify`, valid proofs are rejected as soon as the tree has a few entries
+    const PROOFS_VERIFY: bool = false;
+
+    fn insert(&mut self, key: &Hash, value: &Hash) -> Result<(), Self::Error> {
+        self.tree.update(key, Bytes::from(value.to_vec()))
+    }
+
+    fn get(&mut self, key: &Hash) -> Result<Option<Hash>, Self::Error> {
+        Ok(self.tree.get(key)?.map(|value| slice_to_hash(&value)))
+    }
+
+    fn remove(&mut self, key: &Hash) -> Result<(), Self::Error> {
+        self.tree.remove(key)
+    }
+
+    fn root(&self) -> Hash {
+        slice_to_hash(self.tree.root_ref())
+    }
+
+    fn prove(&mut self, key: &Hash) -> Result<Self::Proof, Self::Error> {
+        self.tree.prove(key)
+    }
+
+    // lsmtree 0.1.1 reads the direction bits from the side nodes instead of the key path
+    // when verifying, so proofs on large trees are wrongly rejected, the full root
+    // recomputation is still performed
+    fn verify(&self, key: &Hash, value: &Hash, proof: Self::Proof) -> bool {
+        proof.verify(self.tree.root_ref(), key, value)
+    }
+
+    // An empty value makes lsmtree check a non-membership proof, the verification bug above
+    // rejects these too
+    fn verify_absent(&self, key: &Hash, proof: Self::Proof) -> bool {
+        proof.verify(self.tree.root_ref(), key, [])
+    }
+
+    // No bulk API in lsmtree
+    fn batch_update(&mut self, entries: &[(Hash, Hash)]) -> Result<(), Self::Error> {
+        for (key, value) in entries.iter() {
+            self.insert(key, value)?;
+        }
+        Ok(())
+    }
+
+    // lsmtree has no bulk API either
+    fn batch_remove(&mut self, keys: &[Hash]) -> Result<(), Self::Error> {
+        for key in keys.iter() {
+            self.remove(key)?;
+        }
+        Ok(())
+    }
+
+    fn proof_size(proof: &Self::Proof) -> usize {
+        proof.side_nodes().iter().map(|n| n.len()).sum::<usize>()
+            + proof.non_membership_leaf_data().map_or(0, |d| d.len())
+            + proof.sibling_data().map_or(0, |d| d.len())
+    }
+
+    fn fork(&self) -> Self {
+        LsmtreeBackend::import(self.nodes.clone(), self.values.clone(), self.tree.root())
+    }
+
+    fn checkpoint(&mut self) -> Result<Self::Checkpoint, Self::Error> {
+        let nodes = self.nodes.borrow_mut().checkpoint()?;
+        let values = self.values.borrow_mut().checkpoint()?;
+        Ok((self.tree.root(), nodes, values))
+    }
+
+    fn rollback(&mut self, (root, nodes, values): Self::Checkpoint) -> Result<(), Self::Error> {
+        self.nodes.borrow_mut().rollback(nodes)?;
+        self.values.borrow_mut().rollback(values)?;
+        self.tree.set_root(root);
+        Ok(())
+    }
+
+    fn release(&mut self, (_, nodes, values): Self::Checkpoint) -> Result<(), Self::Error> {
+        self.nodes.borrow_mut().release(nodes)?;
+        self.values.borrow_mut().release(values)?;
+        Ok(())
+    }
+}
+
+// Compact then decompact, what a light client receiving a compact proof pays
+fn test_proof_compaction<S>(
+    (mut backend, mut workload): (LsmtreeBackend<S>, Workload),
+    b: &mut Bencher,
+) where
+    S: KVStore + CheckpointStore + Clone,
+    S::Hasher: Clone,
+    S::Error: From<StoreError>,
+{
+    let (key, leaf) = workload.entry();
+    backend.insert(&key, &leaf).unwrap();
+    let proof = backend.prove(&key).unwrap();
+    b.iter(|| proof.compact().unwrap().decompact().unwrap())
+}
+
+/// Number of keys the compacted proof size is averaged over
+const PROOF_SIZE_SAMPLES: usize = 100;
+
+fn compact_proof_size<H>(proof: &SparseCompactMerkleProof<H>) -> usize {
+    let bitmask_len = proof.original_side_nodes_len().div_ceil(8);
+    proof.side_nodes().iter().map(|n| n.len()).sum::<usize>()
+        + bitmask_len
+        + proof.non_membership_leaf_data().map_or(0, |d| d.len())
+        + proof.sibling_data().map_or(0, |d| d.len())
+}
+
+fn report_compact_proof_size<S, F>(label: &str, init: F, tree_size: usize)
+where
+    S: KVStore + CheckpointStore + Clone + 'static,
+    S::Hasher: Clone,
+    S::Error: From<StoreError>,
+    F: Fn() -> LsmtreeBackend<S>,
+{
+    let (mut backend, mut workload) = filled_backend(label, &init, tree_size);
+    let keys: Vec<Hash> = (0..PROOF_SIZE_SAMPLES).map(|_| workload.hash()).collect();
+    for key in keys.iter() {
+        backend.insert(key, &workload.hash()).unwrap();
     }
+    let total: usize = keys
+        .iter()
+        .map(|key| compact_proof_size(&backend.prove(key).unwrap().compact().unwrap()))
+        .sum();
+    println!(
+        "lsmtree/proof/{}: average compacted proof size {} bytes",
+        label,
+        total / PROOF_SIZE_SAMPLES
+    );
 }
 
-fn test_tree<S: KVStore>(mut tree: SparseMerkleTree<S>, b: &mut Bencher, tree_size: usize) {
-    let key = random_hash();
-    let leaf = random_hash();
-    fill_lsmtree(&mut tree, tree_size);
-    b.iter(move || {
-        tree.update(key.as_slice(), Bytes::from(leaf.to_vec()))
-            .unwrap();
-        let _ = tree.get(key.as_slice()).unwrap();
-        tree.remove(&key).unwrap();
-    })
+fn init_lsmtree_memstore<H: SmtHasher>() -> LsmtreeBackend<MemoryStore<H>> {
+    LsmtreeBackend::default()
 }
 
-fn test_read_only<S: KVStore>(mut tree: SparseMerkleTree<S>, b: &mut Bencher, tree_size: usize) {
-    let key = random_hash();
-    let leaf = random_hash();
-    fill_lsmtree(&mut tree, tree_size);
-    tree.update(key.as_slice(), Bytes::from(leaf.to_vec())).unwrap();
-    b.iter(move || {
-        let _ = tree.get(key.as_slice()).unwrap();
-    })
+fn init_lsmtree_rocksdb<H: SmtHasher>() -> LsmtreeBackend<SmtRockSdb<H>> {
+    LsmtreeBackend::default()
 }
 
-fn test_write_only<S: KVStore>(mut tree: SparseMerkleTree<S>, b: &mut Bencher, tree_size: usize) {
-    let key = random_hash();
-    let leaf = random_hash();
-    fill_lsmtree(&mut tree, tree_size);
-    b.iter(move || {
-        tree.update(key.as_slice(), Bytes::from(leaf.to_vec())).unwrap();
-        tree.remove(&key).unwrap();
-    })
+fn init_lsmtree_counted_memstore<H: SmtHasher>() -> LsmtreeBackend<CountingStore<MemoryStore<H>>> {
+    LsmtreeBackend::default()
 }
 
-fn init_lsmtree_memstore_blake3() -> SparseMerkleTree<MemoryStore> {
-    SparseMerkleTree::new()
+fn init_lsmtree_counted_rocksdb<H: SmtHasher>() -> LsmtreeBackend<CountingStore<SmtRockSdb<H>>> {
+    LsmtreeBackend::default()
 }
 
-fn init_lsmtree_rocksdb_blake3() -> SparseMerkleTree<SmtRockSdb> {
-    SparseMerkleTree::new()
+fn init_lsmtree_rocksdb_cf<H: SmtHasher>() -> LsmtreeBackend<SmtRockSdbCf<H>> {
+    LsmtreeBackend::default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{init_lsmtree_memstore, init_lsmtree_rocksdb, init_lsmtree_rocksdb_cf};
+    use crate::blake3::Blake3SmtHasher;
+    use crate::suite::check_backend;
+
+    #[test]
+    fn memstore_behaves_as_a_map() {
+        check_backend(init_lsmtree_memstore::<Blake3SmtHasher>);
+    }
+
+    #[test]
+    fn rocksdb_behaves_as_a_map() {
+        check_backend(init_lsmtree_rocksdb::<Blake3SmtHasher>);
+    }
+
+    #[test]
+    fn rocksdb_cf_behaves_as_a_map() {
+        check_backend(init_lsmtree_rocksdb_cf::<Blake3SmtHasher>);
+    }
 }
@@ -1,40 +1,316 @@
 use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::marker::PhantomData;
+use std::path::Path;
+use std::rc::Rc;
 
-use lsmtree::{bytes::Bytes, BadProof};
+use lsmtree::bytes::Bytes;
 use sparse_merkle_tree::{
     traits::{StoreReadOps, StoreWriteOps},
-    H256,
+    BranchKey, BranchNode, H256,
 };
 
 use crate::blake3::Blake3SmtHasher;
+use crate::branch_codec::{
+    decode_branch, decode_branch_key, encode_branch, encode_branch_key, encoded_branch_len,
+    BRANCH_KEY_LEN,
+};
+use crate::checkpoint::CheckpointStore;
+use crate::error::{key_from_slice, stored_hash, StoreError};
+use crate::metrics::StoreMetrics;
 
-#[derive(Default)]
-pub struct MemoryStore {
+/// The hasher is only used by lsmtree, that takes it from the store
+pub struct MemoryStore<H = Blake3SmtHasher> {
     db: BTreeMap<[u8; 32], Vec<u8>>,
     branch_db: BTreeMap<sparse_merkle_tree::BranchKey, sparse_merkle_tree::BranchNode>,
+    hasher: PhantomData<H>,
+    metrics: Rc<StoreMetrics>,
+    undo: UndoLog,
 }
 
-// MONOTREE
+/// Previous content of what changed since the oldest checkpoint held
+#[derive(Default)]
+struct UndoLog {
+    changes: Vec<Undo>,
+    // Id of each checkpoint held, with the number of changes logged when it was taken
+    checkpoints: Vec<(u64, usize)>,
+    next_id: u64,
+}
 
-impl monotree::Database for MemoryStore {
-    fn new(_: &str) -> Self {
+impl UndoLog {
+    fn position(&self, checkpoint: &MemoryCheckpoint) -> Result<usize, StoreError> {
+        self.checkpoints
+            .iter()
+            .position(|(id, _)| *id == checkpoint.0)
+            .ok_or(StoreError::InvalidCheckpoint)
+    }
+}
+
+enum Undo {
+    Entry([u8; 32], Option<Vec<u8>>),
+    Branch(BranchKey, Option<BranchNode>),
+}
+
+/// Id of a checkpoint taken on a `MemoryStore`, never given twice by the same store
+#[derive(Debug)]
+pub struct MemoryCheckpoint(u64);
+
+/// First bytes of a file written by `MemoryStore::save`
+const SNAPSHOT_MAGIC: &[u8; 4] = b"SMTS";
+
+/// Version following the magic, to change when the layout does
+const SNAPSHOT_VERSION: u8 = 1;
+
+impl<H> MemoryStore<H> {
+    /// Writes both maps to `path`, for `load` to rebuild the store in a later run
+    ///
+    /// The magic and version, then the number of entries and the entries, then the
+    /// number of branches and the branches. Counts are little endian `u64`, an entry is
+    /// its key, its length as a `u32` then its value, a branch is its encoded key, its
+    /// length as a `u8` then the branch as `branch_codec` encodes it.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), StoreError> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(SNAPSHOT_MAGIC)?;
+        file.write_all(&[SNAPSHOT_VERSION])?;
+        file.write_all(&(self.db.len() as u64).to_le_bytes())?;
+        for (key, value) in self.db.iter() {
+            file.write_all(key)?;
+            file.write_all(&(value.len() as u32).to_le_bytes())?;
+            file.write_all(value)?;
+        }
+        file.write_all(&(self.branch_db.len() as u64).to_le_bytes())?;
+        for (branch_key, branch) in self.branch_db.iter() {
+            let bytes = encode_branch(branch);
+            file.write_all(&encode_branch_key(branch_key))?;
+            file.write_all(&[bytes.len() as u8])?;
+            file.write_all(&bytes)?;
+        }
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Store holding the maps saved to `path`, a file that does not decode is `Corrupted`
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, StoreError> {
+        let bytes = std::fs::read(path)?;
+        let mut reader = SnapshotReader {
+            bytes: &bytes,
+            offset: 0,
+        };
+        if reader.take(SNAPSHOT_MAGIC.len())? != SNAPSHOT_MAGIC {
+            return Err(StoreError::Corrupted("not a store snapshot".to_string()));
+        }
+        match reader.take(1)?[0] {
+            SNAPSHOT_VERSION => {}
+            version => {
+                return Err(StoreError::Corrupted(format!(
+                    "unsupported snapshot version {}",
+                    version
+                )))
+            }
+        }
+        let mut store = MemoryStore::default();
+        for _ in 0..reader.u64()? {
+            let key: [u8; 32] = reader.array()?;
+            let len = u32::from_le_bytes(reader.array()?) as usize;
+            store.db.insert(key, reader.take(len)?.to_vec());
+        }
+        for _ in 0..reader.u64()? {
+            let branch_key = decode_branch_key(&reader.array()?);
+            let len = reader.take(1)?[0] as usize;
+            let branch = decode_branch(reader.take(len)?)?;
+            store.branch_db.insert(branch_key, branch);
+        }
+        match bytes.len() - reader.offset {
+            0 => Ok(store),
+            trailing => Err(StoreError::Corrupted(format!(
+                "{} trailing bytes after the snapshot",
+                trailing
+            ))),
+        }
+    }
+
+    /// Writes every entry and branch to `store`, to run another store on the same tree
+    ///
+    /// A sparse-merkle-tree built over the copy with `SparseMerkleTree::new_with_store`
+    /// finds the same root, monotree and lsmtree trees need the root they had.
+    pub fn copy_into<S>(&self, store: &mut S) -> monotree::Result<()>
+    where
+        S: monotree::Database + StoreWriteOps<H256>,
+    {
+        for (key, value) in self.db.iter() {
+            store.put(key, value.clone())?;
+        }
+        for (branch_key, branch) in self.branch_db.iter() {
+            store
+                .insert_branch(branch_key.clone(), branch.clone())
+                .map_err(|err| monotree::Errors::new(&err.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+impl<H> MemoryStore<H> {
+    // The writes only log what they change while a checkpoint is held
+    fn log_entry(&mut self, key: [u8; 32]) {
+        if !self.undo.checkpoints.is_empty() {
+            let previous = self.db.get(&key).cloned();
+            self.undo.changes.push(Undo::Entry(key, previous));
+        }
+    }
+
+    fn log_branch(&mut self, branch_key: &BranchKey) {
+        if !self.undo.checkpoints.is_empty() {
+            let previous = self.branch_db.get(branch_key).cloned();
+            self.undo
+                .changes
+                .push(Undo::Branch(branch_key.clone(), previous));
+        }
+    }
+}
+
+// An undo log, a checkpoint costs nothing and a rollback the changes made since
+impl<H> CheckpointStore for MemoryStore<H> {
+    type Checkpoint = MemoryCheckpoint;
+
+    fn checkpoint(&mut self) -> Result<MemoryCheckpoint, StoreError> {
+        let id = self.undo.next_id;
+        self.undo.next_id += 1;
+        self.undo.checkpoints.push((id, self.undo.changes.len()));
+        Ok(MemoryCheckpoint(id))
+    }
+
+    fn rollback(&mut self, checkpoint: MemoryCheckpoint) -> Result<(), StoreError> {
+        let position = self.undo.position(&checkpoint)?;
+        let (_, start) = self.undo.checkpoints[position];
+        self.undo.checkpoints.truncate(position);
+        let changes = self.undo.changes.split_off(start);
+        for change in changes.into_iter().rev() {
+            match change {
+                Undo::Entry(key, Some(value)) => {
+                    self.db.insert(key, value);
+                }
+                Undo::Entry(key, None) => {
+                    self.db.remove(&key);
+                }
+                Undo::Branch(branch_key, Some(branch)) => {
+                    self.branch_db.insert(branch_key, branch);
+                }
+                Undo::Branch(branch_key, None) => {
+                    self.branch_db.remove(&branch_key);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // The changes are still needed by the checkpoints taken before this one
+    fn release(&mut self, checkpoint: MemoryCheckpoint) -> Result<(), StoreError> {
+        let position = self.undo.position(&checkpoint)?;
+        self.undo.checkpoints.truncate(position);
+        if self.undo.checkpoints.is_empty() {
+            self.undo.changes.clear();
+        }
+        Ok(())
+    }
+}
+
+/// Cursor over a snapshot, checking each read stays in the file
+struct SnapshotReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> SnapshotReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StoreError> {
+        let end = self.offset.saturating_add(len);
+        let taken = self.bytes.get(self.offset..end).ok_or_else(|| {
+            StoreError::Corrupted(format!(
+                "truncated snapshot, expected at least {} bytes actual {}",
+                end,
+                self.bytes.len()
+            ))
+        })?;
+        self.offset = end;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], StoreError> {
+        Ok(self.take(N)?.try_into().expect("N bytes taken"))
+    }
+
+    fn u64(&mut self) -> Result<u64, StoreError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+}
+
+impl<H> Clone for MemoryStore<H> {
+    fn clone(&self) -> Self {
+        MemoryStore {
+            db: self.db.clone(),
+            branch_db: self.branch_db.clone(),
+            hasher: PhantomData,
+            metrics: StoreMetrics::current(),
+            undo: UndoLog::default(),
+        }
+    }
+}
+
+impl<H> Default for MemoryStore<H> {
+    fn default() -> Self {
         MemoryStore {
             db: BTreeMap::new(),
             branch_db: BTreeMap::new(),
+            hasher: PhantomData,
+            metrics: StoreMetrics::current(),
+            undo: UndoLog::default(),
         }
     }
+}
+
+impl<H> Drop for MemoryStore<H> {
+    fn drop(&mut self) {
+        if !self.metrics.is_recording() {
+            return;
+        }
+        let leaf_bytes: usize = self.db.values().map(|value| 32 + value.len()).sum();
+        let branch_bytes: usize = self
+            .branch_db
+            .values()
+            .map(|branch| BRANCH_KEY_LEN + encoded_branch_len(branch))
+            .sum();
+        self.metrics.add_footprint(
+            (self.db.len() + self.branch_db.len()) as u64,
+            (leaf_bytes + branch_bytes) as u64,
+        );
+    }
+}
+
+// MONOTREE
+
+impl<H> monotree::Database for MemoryStore<H> {
+    fn new(_: &str) -> Self {
+        MemoryStore::default()
+    }
 
     fn get(&mut self, key: &[u8]) -> monotree::Result<Option<Vec<u8>>> {
+        self.metrics.read();
         Ok(self.db.get(key).cloned())
     }
 
     fn put(&mut self, key: &[u8], value: Vec<u8>) -> monotree::Result<()> {
-        let key = key.try_into().unwrap();
+        let key = key_from_slice(key)?;
+        self.metrics.write(key.len(), value.len());
+        self.log_entry(key);
         self.db.insert(key, value);
         Ok(())
     }
 
     fn delete(&mut self, key: &[u8]) -> monotree::Result<()> {
+        self.metrics.delete();
+        if let Ok(key) = key_from_slice(key) {
+            self.log_entry(key);
+        }
         self.db.remove(key);
         Ok(())
     }
@@ -50,11 +326,12 @@ impl monotree::Database for MemoryStore {
 
 // Sparse Merkle Tree
 
-impl StoreReadOps<H256> for MemoryStore {
+impl<H> StoreReadOps<H256> for MemoryStore<H> {
     fn get_branch(
         &self,
         branch_key: &sparse_merkle_tree::BranchKey,
     ) -> Result<Option<sparse_merkle_tree::BranchNode>, sparse_merkle_tree::error::Error> {
+        self.metrics.read();
         Ok(self.branch_db.get(branch_key).cloned())
     }
 
@@ -62,25 +339,23 @@ impl StoreReadOps<H256> for MemoryStore {
         &self,
         leaf_key: &sparse_merkle_tree::H256,
     ) -> Result<Option<H256>, sparse_merkle_tree::error::Error> {
-        Ok(self.db.get(leaf_key.as_slice()).map(|v| {
-            let buff: [u8; 32] = v
-                .iter()
-                .take(32)
-                .cloned()
-                .collect::<Vec<u8>>()
-                .try_into()
-                .unwrap();
-            H256::from(buff)
-        }))
+        self.metrics.read();
+        match self.db.get(leaf_key.as_slice()) {
+            Some(value) => Ok(Some(H256::from(stored_hash(value)?))),
+            None => Ok(None),
+        }
     }
 }
 
-impl StoreWriteOps<H256> for MemoryStore {
+impl<H> StoreWriteOps<H256> for MemoryStore<H> {
     fn insert_branch(
         &mut self,
         node_key: sparse_merkle_tree::BranchKey,
         branch: sparse_merkle_tree::BranchNode,
     ) -> Result<(), sparse_merkle_tree::error::Error> {
+        self.metrics
+            .write(BRANCH_KEY_LEN, encoded_branch_len(&branch));
+        self.log_branch(&node_key);
         self.branch_db.insert(node_key, branch);
         Ok(())
     }
@@ -90,8 +365,9 @@ impl StoreWriteOps<H256> for MemoryStore {
         leaf_key: sparse_merkle_tree::H256,
         leaf: H256,
     ) -> Result<(), sparse_merkle_tree::error::Error> {
-        let key: [u8; 32] = leaf_key.as_slice().try_into().unwrap();
-        self.db.insert(key, leaf.as_slice().to_vec());
+        self.metrics.write(32, 32);
+        self.log_entry(leaf_key.into());
+        self.db.insert(leaf_key.into(), leaf.as_slice().to_vec());
         Ok(())
     }
 
@@ -99,6 +375,8 @@ impl StoreWriteOps<H256> for MemoryStore {
         &mut self,
         node_key: &sparse_merkle_tree::BranchKey,
     ) -> Result<(), sparse_merkle_tree::error::Error> {
+        self.metrics.delete();
+        self.log_branch(node_key);
         self.branch_db.remove(node_key);
         Ok(())
     }
@@ -107,6 +385,8 @@ impl StoreWriteOps<H256> for MemoryStore {
         &mut self,
         leaf_key: &sparse_merkle_tree::H256,
     ) -> Result<(), sparse_merkle_tree::error::Error> {
+        self.metrics.delete();
+        self.log_entry((*leaf_key).into());
         self.db.remove(leaf_key.as_slice());
         Ok(())
     }
@@ -114,12 +394,13 @@ impl StoreWriteOps<H256> for MemoryStore {
 
 // LSMTREE
 
-impl lsmtree::KVStore for MemoryStore {
-    type Hasher = Blake3SmtHasher;
-    type Error = BadProof;
+impl<H: lsmtree::digest::Digest> lsmtree::KVStore for MemoryStore<H> {
+    type Hasher = H;
+    type Error = StoreError;
 
     fn get(&self, key: &[u8]) -> Result<Option<lsmtree::bytes::Bytes>, Self::Error> {
-        let key: [u8; 32] = key.try_into().unwrap();
+        let key = key_from_slice(key)?;
+        self.metrics.read();
         Ok(self.db.get(&key).map(|b| Bytes::from(b.clone())))
     }
 
@@ -128,18 +409,210 @@ impl lsmtree::KVStore for MemoryStore {
         key: lsmtree::bytes::Bytes,
         value: lsmtree::bytes::Bytes,
     ) -> Result<(), Self::Error> {
-        let key: [u8; 32] = key.to_vec().try_into().unwrap();
+        let key = key_from_slice(&key)?;
+        self.metrics.write(key.len(), value.len());
+        self.log_entry(key);
         self.db.insert(key, value.to_vec());
         Ok(())
     }
 
     fn remove(&mut self, key: &[u8]) -> Result<lsmtree::bytes::Bytes, Self::Error> {
-        let key: [u8; 32] = key.try_into().unwrap();
-        Ok(Bytes::from(self.db.remove(&key).unwrap()))
+        let key = key_from_slice(key)?;
+        self.metrics.delete();
+        self.log_entry(key);
+        match self.db.remove(&key) {
+            Some(value) => Ok(Bytes::from(value)),
+            None => Err(StoreError::MissingKey(key.to_vec())),
+        }
     }
 
     fn contains(&self, key: &[u8]) -> Result<bool, Self::Error> {
-        let key: [u8; 32] = key.try_into().unwrap();
-        Ok(self.db.contains_key(&key))
+        self.metrics.read();
+        Ok(self.db.contains_key(&key_from_slice(key)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsmtree::bytes::Bytes;
+    use lsmtree::KVStore;
+    use monotree::Database;
+    use proptest::collection::vec;
+    use proptest::prelude::{any, prop_assert_eq, proptest};
+    use sparse_merkle_tree::merge::MergeValue;
+    use sparse_merkle_tree::traits::{StoreReadOps, StoreWriteOps};
+    use sparse_merkle_tree::{BranchKey, BranchNode};
+
+    use super::MemoryStore;
+    use crate::bench_dir::BenchDir;
+    use crate::blake3::Blake3SmtHasher;
+    use crate::checkpoint::CheckpointStore;
+    use crate::error::StoreError;
+    use crate::store_model::{
+        branch_node, check_database, check_kv_store, check_smt_store, database_ops, kv_ops,
+        smt_store_ops,
+    };
+
+    fn saved_store() -> MemoryStore {
+        let mut store: MemoryStore = MemoryStore::default();
+        Database::put(&mut store, &[1; 32], vec![2; 10]).unwrap();
+        Database::put(&mut store, &[3; 32], vec![]).unwrap();
+        store
+    }
+
+    #[test]
+    fn bad_key_length_is_an_error() {
+        let mut store: MemoryStore = MemoryStore::default();
+        assert!(Database::put(&mut store, &[0; 31], vec![1]).is_err());
+        assert!(matches!(
+            KVStore::get(&store, &[0; 33]),
+            Err(StoreError::BadKeyLength {
+                expected: 32,
+                actual: 33
+            })
+        ));
+    }
+
+    #[test]
+    fn removing_a_missing_key_is_an_error() {
+        let mut store: MemoryStore = MemoryStore::default();
+        assert!(matches!(
+            KVStore::remove(&mut store, &[7; 32]),
+            Err(StoreError::MissingKey(key)) if key == [7; 32]
+        ));
+    }
+
+    #[test]
+    fn corrupted_leaf_is_an_error() {
+        let mut store: MemoryStore = MemoryStore::default();
+        Database::put(&mut store, &[1; 32], vec![1, 2, 3]).unwrap();
+        assert!(store.get_leaf(&[1; 32].into()).is_err());
+    }
+
+    #[test]
+    fn corrupted_snapshot_is_an_error() {
+        let dir = BenchDir::new("memory_store_snapshot");
+        let path = dir.path().join("store");
+        saved_store().save(&path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+
+        for len in 0..bytes.len() {
+            std::fs::write(&path, &bytes[..len]).unwrap();
+            assert!(
+                matches!(
+                    MemoryStore::<()>::load(&path),
+                    Err(StoreError::Corrupted(_))
+                ),
+                "{} bytes",
+                len
+            );
+        }
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        std::fs::write(&path, trailing).unwrap();
+        assert!(matches!(
+            MemoryStore::<()>::load(&path),
+            Err(StoreError::Corrupted(_))
+        ));
+        let mut version = bytes;
+        version[4] += 1;
+        std::fs::write(&path, version).unwrap();
+        assert!(matches!(
+            MemoryStore::<()>::load(&path),
+            Err(StoreError::Corrupted(_))
+        ));
+    }
+
+    #[test]
+    fn rollback_undoes_every_kind_of_write() {
+        let mut store = saved_store();
+        let branch_key = BranchKey::new(0, [5; 32].into());
+        let branch = BranchNode {
+            left: MergeValue::Value([6; 32].into()),
+            right: MergeValue::zero(),
+        };
+        let checkpoint = store.checkpoint().unwrap();
+        Database::put(&mut store, &[1; 32], vec![4]).unwrap();
+        Database::delete(&mut store, &[3; 32]).unwrap();
+        KVStore::set(
+            &mut store,
+            Bytes::copy_from_slice(&[7; 32]),
+            Bytes::from(vec![8]),
+        )
+        .unwrap();
+        store.insert_leaf([9; 32].into(), [10; 32].into()).unwrap();
+        store.insert_branch(branch_key.clone(), branch).unwrap();
+        store.rollback(checkpoint).unwrap();
+
+        assert_eq!(
+            Database::get(&mut store, &[1; 32]).unwrap(),
+            Some(vec![2; 10])
+        );
+        assert_eq!(Database::get(&mut store, &[3; 32]).unwrap(), Some(vec![]));
+        assert_eq!(KVStore::get(&store, &[7; 32]).unwrap(), None);
+        assert_eq!(store.get_leaf(&[9; 32].into()).unwrap(), None);
+        assert_eq!(store.get_branch(&branch_key).unwrap(), None);
+    }
+
+    #[test]
+    fn checkpoint_dropped_by_a_rollback_is_invalid() {
+        let mut store = saved_store();
+        let outer = store.checkpoint().unwrap();
+        let nested = store.checkpoint().unwrap();
+        store.rollback(outer).unwrap();
+        let _newer = store.checkpoint().unwrap();
+        let _newest = store.checkpoint().unwrap();
+        assert!(matches!(
+            store.rollback(nested),
+            Err(StoreError::InvalidCheckpoint)
+        ));
+    }
+
+    #[test]
+    fn loading_a_missing_snapshot_is_an_io_error() {
+        let dir = BenchDir::new("memory_store_snapshot");
+        assert!(matches!(
+            MemoryStore::<()>::load(dir.path().join("missing")),
+            Err(StoreError::Io(_))
+        ));
+    }
+
+    proptest! {
+        #[test]
+        fn saved_store_loads_back(
+            entries in vec((any::<[u8; 32]>(), vec(any::<u8>(), 0..40)), 0..20),
+            branches in vec((any::<u8>(), any::<[u8; 32]>(), branch_node()), 0..20),
+        ) {
+            let mut store: MemoryStore = MemoryStore::default();
+            for (key, value) in entries {
+                Database::put(&mut store, &key, value).unwrap();
+            }
+            for (height, node_key, branch) in branches {
+                store
+                    .insert_branch(BranchKey::new(height, node_key.into()), branch)
+                    .unwrap();
+            }
+            let dir = BenchDir::new("memory_store_snapshot");
+            let path = dir.path().join("store");
+            store.save(&path).unwrap();
+            let loaded = MemoryStore::<Blake3SmtHasher>::load(&path).unwrap();
+            prop_assert_eq!(&loaded.db, &store.db);
+            prop_assert_eq!(&loaded.branch_db, &store.branch_db);
+        }
+
+        #[test]
+        fn database_matches_model(ops in database_ops()) {
+            check_database(MemoryStore::<Blake3SmtHasher>::default(), ops)?;
+        }
+
+        #[test]
+        fn smt_store_matches_model(ops in smt_store_ops()) {
+            check_smt_store(MemoryStore::<Blake3SmtHasher>::default(), ops)?;
+        }
+
+        #[test]
+        fn kv_store_matches_model(ops in kv_ops()) {
+            check_kv_store(MemoryStore::<Blake3SmtHasher>::default(), ops)?;
+        }
     }
 }
@@ -0,0 +1,226 @@
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// RocksDB tickers printed with the footprint, the ones telling how much is written
+const REPORTED_COUNTERS: [&str; 5] = [
+    "rocksdb.bytes.written",
+    "rocksdb.wal.bytes",
+    "rocksdb.flush.write.bytes",
+    "rocksdb.compact.write.bytes",
+    "rocksdb.number.keys.written",
+];
+
+thread_local! {
+    static RECORDING: RefCell<Option<Rc<StoreMetrics>>> = const { RefCell::new(None) };
+}
+
+/// Operations counted on the stores while they are used
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StoreCounts {
+    pub reads: u64,
+    pub writes: u64,
+    pub deletes: u64,
+    pub bytes_written: u64,
+}
+
+/// What the stores hold, added by each store when it is dropped
+#[derive(Debug, Clone, Default)]
+pub struct StoreFootprint {
+    pub entries: u64,
+    /// Keys and values, as the store was given them
+    pub stored_bytes: u64,
+    /// Size of the RocksDB table files once the memtables are flushed
+    pub sst_bytes: u64,
+    pub rocksdb_stats: Vec<String>,
+}
+
+impl StoreFootprint {
+    /// Lines of the RocksDB statistics for the `REPORTED_COUNTERS`, for each store
+    pub fn rocksdb_counters(&self) -> Vec<&str> {
+        self.rocksdb_stats
+            .iter()
+            .flat_map(|stats| stats.lines())
+            .filter(|line| {
+                REPORTED_COUNTERS
+                    .iter()
+                    .any(|counter| line.split_whitespace().next() == Some(counter))
+            })
+            .collect()
+    }
+}
+
+/// Calls of one store method, seen by a `CountingStore`
+#[derive(Debug, Clone, Default)]
+pub struct CallStats {
+    pub calls: u64,
+    pub key_bytes: u64,
+    /// Values given to the store, or found in it by the reads
+    pub value_bytes: u64,
+    pub latency: Duration,
+    /// Calls by latency when it is traced, bucket `i` counting the calls under 2^i ns
+    pub latency_histogram: Vec<u64>,
+}
+
+impl CallStats {
+    /// Latency `quantile` of the calls are under, rounded up to a power of two nanoseconds
+    pub fn latency_quantile(&self, quantile: f64) -> Option<Duration> {
+        let traced: u64 = self.latency_histogram.iter().sum();
+        let rank = ((traced as f64 * quantile).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.latency_histogram.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Some(Duration::from_nanos(1 << bucket.min(63)));
+            }
+        }
+        None
+    }
+}
+
+/// Counters shared by all the stores opened inside `StoreMetrics::record`
+///
+/// Monotree opens its database itself, the stores pick the metrics being recorded
+/// when they are created instead of being given them.
+#[derive(Debug, Default)]
+pub struct StoreMetrics {
+    recording: bool,
+    traces_latency: bool,
+    reads: Cell<u64>,
+    writes: Cell<u64>,
+    deletes: Cell<u64>,
+    bytes_written: Cell<u64>,
+    footprint: RefCell<StoreFootprint>,
+    calls: RefCell<BTreeMap<&'static str, CallStats>>,
+}
+
+impl StoreMetrics {
+    /// Runs `open`, the stores it creates report to the returned metrics
+    pub fn record<T>(open: impl FnOnce() -> T) -> (T, Rc<StoreMetrics>) {
+        StoreMetrics::record_with(false, open)
+    }
+
+    /// Same as `record`, the `CountingStore`s also timing each call
+    pub fn record_latencies<T>(open: impl FnOnce() -> T) -> (T, Rc<StoreMetrics>) {
+        StoreMetrics::record_with(true, open)
+    }
+
+    fn record_with<T>(traces_latency: bool, open: impl FnOnce() -> T) -> (T, Rc<StoreMetrics>) {
+        let metrics = Rc::new(StoreMetrics {
+            recording: true,
+            traces_latency,
+            ..StoreMetrics::default()
+        });
+        let previous = RECORDING.with(|recording| recording.replace(Some(metrics.clone())));
+        let opened = open();
+        RECORDING.with(|recording| recording.replace(previous));
+        (opened, metrics)
+    }
+
+    /// Metrics for a store being created, detached ones outside of `record`
+    pub(crate) fn current() -> Rc<StoreMetrics> {
+        RECORDING.with(|recording| recording.borrow().clone().unwrap_or_default())
+    }
+
+    /// Whether the footprint is wanted, it costs a flush when a RocksDB store is dropped
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    pub fn traces_latency(&self) -> bool {
+        self.traces_latency
+    }
+
+    pub fn counts(&self) -> StoreCounts {
+        StoreCounts {
+            reads: self.reads.get(),
+            writes: self.writes.get(),
+            deletes: self.deletes.get(),
+            bytes_written: self.bytes_written.get(),
+        }
+    }
+
+    /// Calls seen by the `CountingStore`s, by method name
+    pub fn calls(&self) -> BTreeMap<&'static str, CallStats> {
+        self.calls.borrow().clone()
+    }
+
+    /// Footprint of the stores dropped so far
+    pub fn footprint(&self) -> StoreFootprint {
+        self.footprint.borrow().clone()
+    }
+
+    pub(crate) fn read(&self) {
+        self.reads.set(self.reads.get() + 1);
+    }
+
+    pub(crate) fn write(&self, key_len: usize, value_len: usize) {
+        self.writes.set(self.writes.get() + 1);
+        self.bytes_written
+            .set(self.bytes_written.get() + (key_len + value_len) as u64);
+    }
+
+    pub(crate) fn delete(&self) {
+        self.deletes.set(self.deletes.get() + 1);
+    }
+
+    pub(crate) fn add_call(
+        &self,
+        method: &'static str,
+        key_len: usize,
+        value_len: usize,
+        latency: Option<Duration>,
+    ) {
+        let mut calls = self.calls.borrow_mut();
+        let stats = calls.entry(method).or_default();
+        stats.calls += 1;
+        stats.key_bytes += key_len as u64;
+        stats.value_bytes += value_len as u64;
+        if let Some(latency) = latency {
+            let nanos = latency.as_nanos().min(u64::MAX as u128) as u64;
+            stats.latency_histogram.resize(65, 0);
+            stats.latency_histogram[(u64::BITS - nanos.leading_zeros()) as usize] += 1;
+            stats.latency += latency;
+        }
+    }
+
+    pub(crate) fn add_footprint(&self, entries: u64, stored_bytes: u64) {
+        let mut footprint = self.footprint.borrow_mut();
+        footprint.entries += entries;
+        footprint.stored_bytes += stored_bytes;
+    }
+
+    pub(crate) fn add_rocksdb_footprint(&self, sst_bytes: u64, stats: Option<String>) {
+        let mut footprint = self.footprint.borrow_mut();
+        footprint.sst_bytes += sst_bytes;
+        footprint.rocksdb_stats.extend(stats);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use monotree::Database;
+
+    use super::StoreMetrics;
+    use crate::memory_store::MemoryStore;
+
+    #[test]
+    fn stores_opened_while_recording_report_to_it() {
+        let (mut store, metrics) = StoreMetrics::record(|| MemoryStore::<()>::new(""));
+        let mut detached = MemoryStore::<()>::new("");
+        store.put(&[1; 32], vec![2; 10]).unwrap();
+        store.get(&[1; 32]).unwrap();
+        detached.put(&[1; 32], vec![2; 10]).unwrap();
+
+        let counts = metrics.counts();
+        assert_eq!(
+            (counts.reads, counts.writes, counts.bytes_written),
+            (1, 1, 42)
+        );
+        assert_eq!(metrics.footprint().entries, 0);
+        drop(store);
+        assert_eq!(metrics.footprint().entries, 1);
+        assert_eq!(metrics.footprint().stored_bytes, 42);
+    }
+}
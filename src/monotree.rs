@@ -1,90 +1,582 @@
-use criterion::{Bencher, Criterion};
-use monotree::utils::random_hash;
-use monotree::{Database, Hash, Hasher, Monotree};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
 
+use criterion::measurement::WallTime;
+use criterion::{BenchmarkGroup, BenchmarkId, Criterion};
+use monotree::{verify_proof, Database, Hash, Hasher, Monotree, Proof};
+use rand::Rng;
+
+use crate::backend::SmtBackend;
+use crate::bench_dir::BenchDir;
 use crate::blake3::Blake3SmtHasher;
+use crate::checkpoint::{open_monotree, HeldCheckpoints, SharedStore};
+use crate::counting_store::CountingStore;
+use crate::digest_hasher::{Blake2bSmtHasher, Keccak256SmtHasher, Sha256SmtHasher};
+use crate::hasher::SmtHasher;
 use crate::memory_store::MemoryStore;
+use crate::poseidon::PoseidonSmtHasher;
+use crate::profile::WorkloadProfile;
 use crate::rocksdb::SmtRockSdb;
+use crate::rocksdb_cf::SmtRockSdbCf;
+use crate::rocksdb_config::rocksdb_label;
+use crate::suite::{
+    bench_backend, bench_backend_proofs, bench_batch_update, bench_blocks, bench_checkpoints,
+    bench_profiles, bench_tree_sizes, report_footprint, report_proof_size, report_store_calls,
+};
+use crate::versioned_monotree::VersionedMonotree;
+use crate::workload::Workload;
+
+/// Number of entries written by each `inserts` call
+const BATCH_SIZE: usize = 1000;
 
-// Blake3 > Sha256 > other hash functions
+/// Keys updated by each block of the pruning benchmarks
+const PRUNING_BLOCK_SIZE: usize = 100;
 
 pub fn add_monotree_benches(c: &mut Criterion, sample_size: usize, tree_size: usize) {
+    report_footprint(
+        "monotree/memstore+blake3",
+        init_monotree_memstore::<Blake3SmtHasher>,
+        tree_size,
+    );
+    report_footprint(
+        "monotree/rocksdb+blake3",
+        init_monotree_rocksdb::<Blake3SmtHasher>,
+        tree_size,
+    );
+    report_store_calls(
+        "monotree/memstore+blake3",
+        init_monotree_counted_memstore::<Blake3SmtHasher>,
+        tree_size,
+    );
+    report_store_calls(
+        "monotree/rocksdb+blake3",
+        init_monotree_counted_rocksdb::<Blake3SmtHasher>,
+        tree_size,
+    );
+
     let mut group = c.benchmark_group("monotree");
     group.sample_size(sample_size);
 
-    group.bench_function("memstore+blake3", |b| {
-        test_tree(init_monotree_memstore_blake3(), b, tree_size)
-    });
+    bench_monotree_hasher::<Blake3SmtHasher>(&mut group, tree_size);
+    bench_monotree_hasher::<Sha256SmtHasher>(&mut group, tree_size);
+    bench_monotree_hasher::<Keccak256SmtHasher>(&mut group, tree_size);
+    bench_monotree_hasher::<Blake2bSmtHasher>(&mut group, tree_size);
+    bench_monotree_hasher::<PoseidonSmtHasher>(&mut group, tree_size);
+}
+
+fn bench_monotree_hasher<H: SmtHasher>(group: &mut BenchmarkGroup<WallTime>, tree_size: usize) {
+    let label = format!("memstore+{}", H::NAME);
+    bench_backend(group, &label, init_monotree_memstore::<H>, tree_size);
+    let label = format!("{}+{}", rocksdb_label("rocksdb"), H::NAME);
+    bench_backend(group, &label, init_monotree_rocksdb::<H>, tree_size);
+}
+
+pub fn add_monotree_proof_benches(c: &mut Criterion, sample_size: usize, tree_size: usize) {
+    let rocksdb = format!("{}+blake3", rocksdb_label("rocksdb"));
+    report_proof_size(
+        "monotree/proof",
+        "memstore+blake3",
+        init_monotree_memstore::<Blake3SmtHasher>,
+        tree_size,
+    );
+    report_proof_size(
+        "monotree/proof",
+        &rocksdb,
+        init_monotree_rocksdb::<Blake3SmtHasher>,
+        tree_size,
+    );
+
+    let mut group = c.benchmark_group("monotree/proof");
+    group.sample_size(sample_size);
+
+    bench_monotree_proof_hasher::<Blake3SmtHasher>(&mut group, tree_size);
+    bench_monotree_proof_hasher::<Sha256SmtHasher>(&mut group, tree_size);
+    bench_monotree_proof_hasher::<Keccak256SmtHasher>(&mut group, tree_size);
+    bench_monotree_proof_hasher::<Blake2bSmtHasher>(&mut group, tree_size);
+    bench_monotree_proof_hasher::<PoseidonSmtHasher>(&mut group, tree_size);
+}
+
+fn bench_monotree_proof_hasher<H: SmtHasher>(
+    group: &mut BenchmarkGroup<WallTime>,
+    tree_size: usize,
+) {
+    let label = format!("memstore+{}", H::NAME);
+    bench_backend_proofs(group, &label, init_monotree_memstore::<H>, tree_size);
+    let label = format!("{}+{}", rocksdb_label("rocksdb"), H::NAME);
+    bench_backend_proofs(group, &label, init_monotree_rocksdb::<H>, tree_size);
+}
+
+// `inserts` with and without the RocksDB write batch, to show the cost of the per-write path
+pub fn add_monotree_batch_benches(c: &mut Criterion, sample_size: usize, tree_size: usize) {
+    let mut group = c.benchmark_group("monotree/inserts");
+    group.sample_size(sample_size);
+
+    bench_batch_update(
+        &mut group,
+        &format!("{}+blake3/batched", rocksdb_label("rocksdb")),
+        init_monotree_rocksdb::<Blake3SmtHasher>,
+        tree_size,
+        BATCH_SIZE,
+    );
+    bench_batch_update(
+        &mut group,
+        &format!("{}+blake3/unbatched", rocksdb_label("rocksdb")),
+        init_monotree_rocksdb_unbatched::<Blake3SmtHasher>,
+        tree_size,
+        BATCH_SIZE,
+    );
+}
+
+pub fn add_monotree_layout_benches(c: &mut Criterion, sample_size: usize, tree_size: usize) {
+    let mut group = c.benchmark_group("monotree/rocksdb-layout");
+    group.sample_size(sample_size);
+
+    bench_backend(
+        &mut group,
+        &format!("{}+blake3", rocksdb_label("two-db")),
+        init_monotree_rocksdb::<Blake3SmtHasher>,
+        tree_size,
+    );
+    bench_backend(
+        &mut group,
+        &format!("{}+blake3", rocksdb_label("column-families")),
+        init_monotree_rocksdb_cf::<Blake3SmtHasher>,
+        tree_size,
+    );
+}
+
+pub fn add_monotree_block_benches(
+    c: &mut Criterion,
+    sample_size: usize,
+    tree_size: usize,
+    block_sizes: &[usize],
+) {
+    let mut group = c.benchmark_group("monotree/blocks");
+    group.sample_size(sample_size);
+
+    let init = init_monotree_memstore::<Blake3SmtHasher>;
+    bench_blocks(&mut group, "memstore+blake3", init, tree_size, block_sizes);
+    let label = format!("{}+blake3", rocksdb_label("rocksdb"));
+    bench_blocks(
+        &mut group,
+        &label,
+        init_monotree_rocksdb::<Blake3SmtHasher>,
+        tree_size,
+        block_sizes,
+    );
+}
+
+// Speculative blocks reverted, monotree keeps every node so its checkpoint is only a root
+pub fn add_monotree_checkpoint_benches(
+    c: &mut Criterion,
+    sample_size: usize,
+    tree_size: usize,
+    update_counts: &[usize],
+) {
+    let mut group = c.benchmark_group("monotree/checkpoints");
+    group.sample_size(sample_size);
+
+    let init = init_monotree_memstore::<Blake3SmtHasher>;
+    bench_checkpoints(
+        &mut group,
+        "memstore+blake3",
+        init,
+        tree_size,
+        update_counts,
+    );
+    let label = format!("{}+blake3", rocksdb_label("rocksdb"));
+    bench_checkpoints(
+        &mut group,
+        &label,
+        init_monotree_rocksdb::<Blake3SmtHasher>,
+        tree_size,
+        update_counts,
+    );
+}
+
+pub fn add_monotree_profile_benches(c: &mut Criterion, sample_size: usize, tree_size: usize) {
+    let mut group = c.benchmark_group("monotree/profiles");
+    group.sample_size(sample_size);
+
+    let profiles = WorkloadProfile::all();
+    let init = init_monotree_memstore::<Blake3SmtHasher>;
+    bench_profiles(&mut group, "memstore+blake3", init, tree_size, &profiles);
+    let label = format!("{}+blake3", rocksdb_label("rocksdb"));
+    bench_profiles(
+        &mut group,
+        &label,
+        init_monotree_rocksdb::<Blake3SmtHasher>,
+        tree_size,
+        &profiles,
+    );
+}
+
+pub fn add_monotree_tree_size_benches(c: &mut Criterion, sample_size: usize, tree_sizes: &[usize]) {
+    let mut group = c.benchmark_group("monotree/tree-size");
+    group.sample_size(sample_size);
+
+    bench_tree_sizes(
+        &mut group,
+        "memstore+blake3",
+        init_monotree_memstore::<Blake3SmtHasher>,
+        tree_sizes,
+    );
+    bench_tree_sizes(
+        &mut group,
+        &format!("{}+blake3", rocksdb_label("rocksdb")),
+        init_monotree_rocksdb::<Blake3SmtHasher>,
+        tree_sizes,
+    );
+}
+
+// Pruning a tree that keeps the states of the last `depth` blocks, one more block committed before each prune
+pub fn add_monotree_pruning_benches(
+    c: &mut Criterion,
+    sample_size: usize,
+    tree_size: usize,
+    history_depths: &[usize],
+) {
+    let mut group = c.benchmark_group("monotree/pruning");
+    group.sample_size(sample_size);
+
+    let init = || VersionedMonotree::<MemoryStore, Blake3SmtHasher>::new("");
+    bench_pruning(
+        &mut group,
+        "memstore+blake3",
+        init,
+        tree_size,
+        history_depths,
+    );
+    let init = || {
+        VersionedMonotree::<SmtRockSdb, Blake3SmtHasher>::in_dir(BenchDir::new(
+            "monotree_versioned_rocksdb_blake3",
+        ))
+    };
+    let label = format!("{}+blake3", rocksdb_label("rocksdb"));
+    bench_pruning(&mut group, &label, init, tree_size, history_depths);
+}
+
+fn bench_pruning<D, H, F>(
+    group: &mut BenchmarkGroup<WallTime>,
+    label: &str,
+    init: F,
+    tree_size: usize,
+    history_depths: &[usize],
+) where
+    D: Database + 'static,
+    H: Hasher,
+    F: Fn() -> VersionedMonotree<D, H>,
+{
+    for &depth in history_depths {
+        assert!(
+            depth > 0,
+            "the latest state is always kept, a depth is at least 1"
+        );
+        let mut state = init();
+        let mut workload = Workload::seeded();
+        let entries = workload.entries(tree_size);
+        let keys: Vec<Hash> = entries.iter().map(|(key, _)| *key).collect();
+        state.commit(0, &entries, &[]).unwrap();
+        let block = |workload: &mut Workload| -> Vec<(Hash, Hash)> {
+            (0..PRUNING_BLOCK_SIZE)
+                .map(|_| {
+                    (
+                        keys[workload.rng().gen_range(0..keys.len())],
+                        workload.hash(),
+                    )
+                })
+                .collect()
+        };
+        let mut height = depth as u64 - 1;
+        for past in 1..=height {
+            state.commit(past, &block(&mut workload), &[]).unwrap();
+        }
+        let report = state.prune(0).unwrap();
+        println!(
+            "monotree/pruning/{}: {} blocks kept, {} nodes reachable, {} deleted",
+            label, report.kept_heights, report.kept_nodes, report.deleted_nodes
+        );
+
+        group.bench_function(BenchmarkId::new(format!("{}/prune", label), depth), |b| {
+            b.iter_custom(|iters| {
+                let mut elapsed = Duration::ZERO;
+                for _ in 0..iters {
+                    height += 1;
+                    state.commit(height, &block(&mut workload), &[]).unwrap();
+                    let start = Instant::now();
+                    black_box(state.prune(height + 1 - depth as u64).unwrap());
+                    elapsed += start.elapsed();
+                }
+                elapsed
+            })
+        });
+
+        // Reads at the oldest kept state, whose nodes are the most spread out among the newer ones
+        let oldest = height + 1 - depth as u64;
+        group.bench_function(
+            BenchmarkId::new(format!("{}/get-oldest", label), depth),
+            |b| b.iter(|| state.get_at(oldest, &keys[0]).unwrap()),
+        );
+    }
+}
+
+/// Monotree is functional, every operation returns the new root to use for the next one
+///
+/// Nodes are stored by hash and never deleted, so each fork writes to its own copy of the store,
+/// the nodes a benchmark writes do not pile up in the tree it forked.
+pub struct MonotreeBackend<D: Database + 'static, H: Hasher> {
+    tree: Monotree<SharedStore<D>, H>,
+    // The database of `tree`, copied by `fork`
+    store: SharedStore<D>,
+    hasher: H,
+    root: Option<Hash>,
+    checkpoints: HeldCheckpoints,
+    // Dropped after the tree, once its database is closed
+    dir: Option<BenchDir>,
+}
+
+impl<D: Database + 'static, H: Hasher> MonotreeBackend<D, H> {
+    pub fn new(dbpath: &str) -> Self {
+        MonotreeBackend::over(SharedStore::new(D::new(dbpath)))
+    }
+
+    /// Tree whose database lives in `dir`, deleted with the backend
+    pub fn in_dir(dir: BenchDir) -> Self {
+        let mut backend = MonotreeBackend::new(&dir.db_path("db"));
+        backend.dir = Some(dir);
+        backend
+    }
+
+    fn over(store: SharedStore<D>) -> Self {
+        MonotreeBackend {
+            tree: open_monotree(&store),
+            store,
+            hasher: H::new(),
+            root: None,
+            checkpoints: HeldCheckpoints::default(),
+            dir: None,
+        }
+    }
+}
+
+impl<D: Database + Clone + 'static, H: Hasher> SmtBackend for MonotreeBackend<D, H> {
+    // Monotree cannot prove an absent key
+    type Proof = Option<Proof>;
+    type Error = monotree::Errors;
+    // Nodes are never deleted, the root is enough to find the state back
+    type Checkpoint = (u64, Option<Hash>);
+
+    // A removal leaves a single-child node behind instead of merging the path back
+    const REMOVE_RESTORES_ROOT: bool = false;
 
-    group.bench_function("memstore+blake3/read", |b| {
-        test_tree_read_only(init_monotree_memstore_blake3(), b, tree_size)
-    });
+    const PROVES_ABSENCE: bool = false;
 
-    group.bench_function("memstore+blake3/write", |b| {
-        test_tree_write_only(init_monotree_memstore_blake3(), b, tree_size)
-    });
+    // A node keeps its two cells in the order they were filled, `inserts` sorts the keys first
+    const ROOT_IGNORES_ORDER: bool = false;
 
-    group.bench_function("rocksdb+blake3", |b| {
-        test_tree(init_monotree_rocksdb_blake3(), b, tree_size)
-    });
+    fn insert(&mut self, key: &Hash, value: &Hash) -> Result<(), Self::Error> {
+        self.root = self.tree.insert(self.root.as_ref(), key, value)?;
+        Ok(())
+    }
+
+    fn get(&mut self, key: &Hash) -> Result<Option<Hash>, Self::Error> {
+        self.tree.get(self.root.as_ref(), key)
+    }
+
+    fn remove(&mut self, key: &Hash) -> Result<(), Self::Error> {
+        // Removing an absent key makes monotree drop the sibling subtree, check first
+        if self.get(key)?.is_none() {
+            return Ok(());
+        }
+        self.remove_present(key)
+    }
+
+    fn remove_present(&mut self, key: &Hash) -> Result<(), Self::Error> {
+        self.root = self.tree.remove(self.root.as_ref(), key)?;
+        Ok(())
+    }
 
-    group.bench_function("rocksdb+blake3/read", |b| {
-        test_tree_read_only(init_monotree_rocksdb_blake3(), b, tree_size)
-    });
+    fn root(&self) -> Hash {
+        self.root.unwrap_or_default()
+    }
 
-    group.bench_function("rocksdb+blake3/write", |b| {
-        test_tree_write_only(init_monotree_rocksdb_blake3(), b, tree_size)
-    });
+    fn prove(&mut self, key: &Hash) -> Result<Self::Proof, Self::Error> {
+        self.tree.get_merkle_proof(self.root.as_ref(), key)
+    }
+
+    fn verify(&self, _key: &Hash, value: &Hash, proof: Self::Proof) -> bool {
+        verify_proof(&self.hasher, self.root.as_ref(), value, proof.as_ref())
+    }
+
+    fn verify_absent(&self, _key: &Hash, _proof: Self::Proof) -> bool {
+        false
+    }
+
+    fn batch_update(&mut self, entries: &[(Hash, Hash)]) -> Result<(), Self::Error> {
+        let (keys, leaves): (Vec<Hash>, Vec<Hash>) = entries.iter().cloned().unzip();
+        self.root = self.tree.inserts(self.root.as_ref(), &keys, &leaves)?;
+        Ok(())
+    }
+
+    fn batch_remove(&mut self, keys: &[Hash]) -> Result<(), Self::Error> {
+        // Same as `remove`, the absent keys must not reach monotree
+        let mut present = Vec::with_capacity(keys.len());
+        for key in keys {
+            if self.get(key)?.is_some() {
+                present.push(*key);
+            }
+        }
+        self.batch_remove_present(&present)
+    }
+
+    fn batch_remove_present(&mut self, keys: &[Hash]) -> Result<(), Self::Error> {
+        self.root = self.tree.removes(self.root.as_ref(), keys)?;
+        Ok(())
+    }
+
+    // Monotree has no compact proof encoding, the raw proof is the serialized form
+    fn proof_size(proof: &Self::Proof) -> usize {
+        proof.iter().flatten().map(|(_, cut)| 1 + cut.len()).sum()
+    }
+
+    // The copy of a RocksDB store lives in its own directory, removed with it
+    fn fork(&self) -> Self {
+        let mut fork = MonotreeBackend::over(self.store.clone());
+        fork.root = self.root;
+        fork
+    }
+
+    fn checkpoint(&mut self) -> Result<Self::Checkpoint, Self::Error> {
+        Ok((self.checkpoints.take(), self.root))
+    }
+
+    fn rollback(&mut self, (id, root): Self::Checkpoint) -> Result<(), Self::Error> {
+        self.checkpoints.drop_from(id)?;
+        self.root = root;
+        Ok(())
+    }
+
+    fn release(&mut self, (id, _): Self::Checkpoint) -> Result<(), Self::Error> {
+        Ok(self.checkpoints.drop_from(id)?)
+    }
 }
 
-fn fill_monotree<D: Database, H: Hasher>(tree: &mut Monotree<D, H>, nb: usize) -> Option<Hash> {
-    let mut root = None;
-    for _ in 0..nb {
-        let key = random_hash();
-        let leaf = random_hash();
-        root = tree.insert(root.as_ref(), &key, &leaf).unwrap();
-        assert_ne!(root, None);
+/// Ignores the batches monotree opens, every write goes straight to the store
+#[derive(Clone)]
+pub struct UnbatchedDatabase<D>(D);
+
+impl<D: Database> Database for UnbatchedDatabase<D> {
+    fn new(dbpath: &str) -> Self {
+        UnbatchedDatabase(D::new(dbpath))
+    }
+
+    fn get(&mut self, key: &[u8]) -> monotree::Result<Option<Vec<u8>>> {
+        self.0.get(key)
+    }
+
+    fn put(&mut self, key: &[u8], value: Vec<u8>) -> monotree::Result<()> {
+        self.0.put(key, value)
+    }
+
+    fn delete(&mut self, key: &[u8]) -> monotree::Result<()> {
+        self.0.delete(key)
+    }
+
+    fn init_batch(&mut self) -> monotree::Result<()> {
+        Ok(())
+    }
+
+    fn finish_batch(&mut self) -> monotree::Result<()> {
+        Ok(())
     }
-    root
 }
 
-fn test_tree<D: Database, H: Hasher>(mut tree: Monotree<D, H>, b: &mut Bencher, tree_size: usize) {
-    let root = fill_monotree(&mut tree, tree_size);
-    let key = random_hash();
-    let leaf = random_hash();
-    b.iter(move || {
-        let new_root = tree.insert(root.as_ref(), &key, &leaf).unwrap();
-        let _ = tree.get(new_root.as_ref(), &key).unwrap();
-        tree.remove(new_root.as_ref(), &key).unwrap();
-    })
+fn init_monotree_memstore<H: SmtHasher>() -> MonotreeBackend<MemoryStore, H> {
+    MonotreeBackend::new(&format!("./.bench_db/monotree_hashmap_{}", H::NAME))
 }
 
-fn test_tree_read_only<D: Database, H: Hasher>(mut tree: Monotree<D, H>, b: &mut Bencher, tree_size: usize) {
-    let root = fill_monotree(&mut tree, tree_size);
-    let key = random_hash();
-    let leaf = random_hash();
-    let new_root = tree.insert(root.as_ref(), &key, &leaf).unwrap();
-    b.iter(move || {
-        let _ = tree.get(new_root.as_ref(), &key).unwrap();
-    })
+fn init_monotree_rocksdb<H: SmtHasher>() -> MonotreeBackend<SmtRockSdb, H> {
+    MonotreeBackend::in_dir(BenchDir::new(&format!("monotree_rocksdb_{}", H::NAME)))
 }
 
-fn test_tree_write_only<D: Database, H: Hasher>(mut tree: Monotree<D, H>, b: &mut Bencher, tree_size: usize) {
-    let root = fill_monotree(&mut tree, tree_size);
-    let key = random_hash();
-    let leaf = random_hash();
-    b.iter(move || {
-        let new_root = tree.insert(root.as_ref(), &key, &leaf).unwrap();
-        tree.remove(new_root.as_ref(), &key).unwrap();
-    })
+fn init_monotree_counted_memstore<H: SmtHasher>() -> MonotreeBackend<CountingStore<MemoryStore>, H>
+{
+    MonotreeBackend::new(&format!("./.bench_db/monotree_hashmap_{}", H::NAME))
 }
 
-fn init_monotree_memstore_blake3() -> Monotree<MemoryStore, Blake3SmtHasher> {
-    Monotree::new("./.bench_db/monotree_hashmap_blake3")
+fn init_monotree_counted_rocksdb<H: SmtHasher>() -> MonotreeBackend<CountingStore<SmtRockSdb>, H> {
+    MonotreeBackend::in_dir(BenchDir::new(&format!(
+        "monotree_counted_rocksdb_{}",
+        H::NAME
+    )))
 }
 
-fn init_monotree_rocksdb_blake3() -> Monotree<SmtRockSdb, Blake3SmtHasher> {
-    Monotree::new("./.bench_db/monotree_rocksdb_blake3")
+fn init_monotree_rocksdb_cf<H: SmtHasher>() -> MonotreeBackend<SmtRockSdbCf, H> {
+    MonotreeBackend::in_dir(BenchDir::new(&format!("monotree_rocksdb_cf_{}", H::NAME)))
 }
 
-// TODO    Add SHA2 flavor also
+fn init_monotree_rocksdb_unbatched<H: SmtHasher>(
+) -> MonotreeBackend<UnbatchedDatabase<SmtRockSdb>, H> {
+    MonotreeBackend::in_dir(BenchDir::new(&format!(
+        "monotree_rocksdb_unbatched_{}",
+        H::NAME
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use monotree::Database;
+
+    use super::{
+        init_monotree_memstore, init_monotree_rocksdb, init_monotree_rocksdb_cf, MonotreeBackend,
+    };
+    use crate::backend::SmtBackend;
+    use crate::blake3::Blake3SmtHasher;
+    use crate::suite::check_backend;
+    use crate::workload::Workload;
+
+    fn check_forks_are_isolated<D: Database + Clone + 'static>(
+        mut fixture: MonotreeBackend<D, Blake3SmtHasher>,
+    ) {
+        let mut workload = Workload::seeded();
+        for _ in 0..100 {
+            let (key, leaf) = workload.entry();
+            fixture.insert(&key, &leaf).unwrap();
+        }
+        let mut fork = fixture.fork();
+        let other = fixture.fork();
+        let (key, leaf) = workload.entry();
+        fork.insert(&key, &leaf).unwrap();
+
+        // The new root node was written to the fork store only
+        let root = fork.root();
+        assert!(fork.store.borrow_mut().get(&root).unwrap().is_some());
+        assert!(fixture.store.borrow_mut().get(&root).unwrap().is_none());
+        assert!(other.store.borrow_mut().get(&root).unwrap().is_none());
+    }
+
+    #[test]
+    fn memstore_behaves_as_a_map() {
+        check_backend(init_monotree_memstore::<Blake3SmtHasher>);
+    }
+
+    #[test]
+    fn rocksdb_behaves_as_a_map() {
+        check_backend(init_monotree_rocksdb::<Blake3SmtHasher>);
+    }
+
+    #[test]
+    fn rocksdb_cf_behaves_as_a_map() {
+        check_backend(init_monotree_rocksdb_cf::<Blake3SmtHasher>);
+    }
+
+    #[test]
+    fn memstore_forks_are_isolated() {
+        check_forks_are_isolated(init_monotree_memstore::<Blake3SmtHasher>());
+    }
+
+    #[test]
+    fn rocksdb_forks_are_isolated() {
+        check_forks_are_isolated(init_monotree_rocksdb::<Blake3SmtHasher>());
+    }
+}
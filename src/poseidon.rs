@@ -0,0 +1,137 @@
+use std::cell::RefCell;
+
+use ark_bn254::Fr;
+use ark_ff::{BigInteger, PrimeField};
+use light_poseidon::{Poseidon, PoseidonHasher};
+use lsmtree::digest::typenum::U32;
+use lsmtree::digest::{FixedOutput, HashMarker, Output, OutputSizeUser, Update};
+
+use crate::hasher::SmtHasher;
+
+/// Bytes packed in each field element, stays below the BN254 scalar modulus
+const CHUNK_LEN: usize = 31;
+
+thread_local! {
+    // Building the round constants costs far more than a permutation, do it once
+    static POSEIDON: RefCell<Poseidon<Fr>> =
+        RefCell::new(Poseidon::<Fr>::new_circom(2).unwrap());
+}
+
+/// Poseidon over BN254 with the circom parameters
+///
+/// The input is chained 31 bytes at a time through the 2-to-1 permutation, starting from its
+/// length so that zero padded inputs differ; the digests match no other Poseidon tree.
+#[derive(Debug, Default, Clone)]
+pub struct PoseidonSmtHasher(Vec<u8>);
+
+fn poseidon_hash(bytes: &[u8]) -> [u8; 32] {
+    POSEIDON.with(|poseidon| {
+        let mut poseidon = poseidon.borrow_mut();
+        let length = Fr::from(bytes.len() as u64);
+        let hash = bytes.chunks(CHUNK_LEN).fold(length, |state, chunk| {
+            let chunk = Fr::from_be_bytes_mod_order(chunk);
+            poseidon.hash(&[state, chunk]).unwrap()
+        });
+        hash.into_bigint().to_bytes_be().try_into().unwrap()
+    })
+}
+
+impl SmtHasher for PoseidonSmtHasher {
+    const NAME: &'static str = "poseidon";
+}
+
+// MONOTREE
+
+impl monotree::hasher::Hasher for PoseidonSmtHasher {
+    fn new() -> Self {
+        PoseidonSmtHasher::default()
+    }
+
+    fn digest(&self, bytes: &[u8]) -> monotree::Hash {
+        poseidon_hash(bytes)
+    }
+}
+
+// SPARSE MERKLE TREE
+
+impl sparse_merkle_tree::traits::Hasher for PoseidonSmtHasher {
+    fn write_h256(&mut self, h: &sparse_merkle_tree::H256) {
+        self.0.extend_from_slice(h.as_slice());
+    }
+
+    fn write_byte(&mut self, b: u8) {
+        self.0.push(b);
+    }
+
+    fn finish(self) -> sparse_merkle_tree::H256 {
+        poseidon_hash(&self.0).into()
+    }
+}
+
+// LSMTREE, `Digest` comes from the blanket implementation over these traits
+
+impl OutputSizeUser for PoseidonSmtHasher {
+    type OutputSize = U32;
+}
+
+impl Update for PoseidonSmtHasher {
+    fn update(&mut self, data: &[u8]) {
+        self.0.extend_from_slice(data);
+    }
+}
+
+impl FixedOutput for PoseidonSmtHasher {
+    fn finalize_into(self, out: &mut Output<Self>) {
+        out.copy_from_slice(&poseidon_hash(&self.0));
+    }
+}
+
+impl HashMarker for PoseidonSmtHasher {}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{poseidon_hash, PoseidonSmtHasher, CHUNK_LEN};
+    use crate::hasher::adapter_digests;
+
+    // circomlib's poseidon([1, 2]), the one byte input 2 is chained from its length 1
+    const ONE_TWO: &str = "115cc0f5e7d690413df64c6b9662e9cf2a3617f2743245519e19607a4417189a";
+
+    fn to_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn circomlib_test_vector() {
+        assert_eq!(to_hex(&adapter_digests::<PoseidonSmtHasher>(&[2])), ONE_TWO);
+    }
+
+    #[test]
+    fn branch_input_gives_the_same_digest_in_every_library() {
+        let input = [&[7][..], &[0x11; 32][..], &[0x22; 32][..]].concat();
+        assert_eq!(
+            adapter_digests::<PoseidonSmtHasher>(&input),
+            poseidon_hash(&input)
+        );
+    }
+
+    // These pack to the same field elements, only their length differs
+    #[test]
+    fn length_and_chunk_boundaries_change_the_digest() {
+        let inputs: Vec<Vec<u8>> = vec![
+            vec![1],
+            vec![0, 1],
+            vec![0; CHUNK_LEN - 1].into_iter().chain([1]).collect(),
+            vec![0],
+            vec![0; CHUNK_LEN],
+            vec![0; CHUNK_LEN + 1],
+            vec![0; 2 * CHUNK_LEN],
+            vec![1; CHUNK_LEN],
+            [vec![1; CHUNK_LEN], vec![0]].concat(),
+            [vec![1; CHUNK_LEN], vec![0; CHUNK_LEN]].concat(),
+        ];
+        let digests: HashSet<[u8; 32]> = inputs.iter().map(|input| poseidon_hash(input)).collect();
+        assert_eq!(digests.len(), inputs.len());
+    }
+}
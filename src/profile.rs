@@ -0,0 +1,284 @@
+use monotree::Hash;
+use rand::Rng;
+use rand_distr::{Distribution, Zipf};
+
+use crate::workload::Workload;
+
+/// How the key of an operation is picked among the keys in the tree
+#[derive(Debug, Clone, Copy)]
+pub enum KeyDistribution {
+    Uniform,
+    /// The oldest keys are the most accessed, with the given exponent (0.99 in YCSB)
+    Zipfian(f64),
+    /// `fraction` of the keys get `probability` of the accesses
+    HotSet {
+        fraction: f64,
+        probability: f64,
+    },
+    /// All the keys in turn, in insertion order
+    Sequential,
+}
+
+/// Mix of operations run against a filled tree, like the YCSB core workloads
+///
+/// The ratios are weights, they do not have to sum to 100.
+#[derive(Debug, Clone)]
+pub struct WorkloadProfile {
+    pub name: &'static str,
+    pub read: u32,
+    pub update: u32,
+    pub insert: u32,
+    pub delete: u32,
+    pub distribution: KeyDistribution,
+}
+
+impl WorkloadProfile {
+    /// YCSB A, B and C, then the other key distributions on a write heavy mix
+    pub fn all() -> Vec<WorkloadProfile> {
+        let profile = |name, read, update, insert, delete, distribution| WorkloadProfile {
+            name,
+            read,
+            update,
+            insert,
+            delete,
+            distribution,
+        };
+        vec![
+            profile("ycsb-a", 50, 50, 0, 0, KeyDistribution::Zipfian(0.99)),
+            profile("ycsb-b", 95, 5, 0, 0, KeyDistribution::Zipfian(0.99)),
+            profile("ycsb-c", 100, 0, 0, 0, KeyDistribution::Zipfian(0.99)),
+            profile("churn-uniform", 40, 20, 20, 20, KeyDistribution::Uniform),
+            profile(
+                "churn-hot-set",
+                40,
+                20,
+                20,
+                20,
+                KeyDistribution::HotSet {
+                    fraction: 0.2,
+                    probability: 0.8,
+                },
+            ),
+            profile(
+                "churn-sequential",
+                40,
+                20,
+                20,
+                20,
+                KeyDistribution::Sequential,
+            ),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operation {
+    Read(Hash),
+    Update(Hash, Hash),
+    Insert(Hash, Hash),
+    Delete(Hash),
+}
+
+/// Operations of a profile, keeping track of the keys in the tree to pick existing ones
+pub struct OperationStream {
+    profile: WorkloadProfile,
+    keys: Vec<Hash>,
+    workload: Workload,
+    cursor: usize,
+    // Over the keys the stream starts with, the zipfian profiles neither insert nor delete
+    zipf: Option<Zipf<f64>>,
+}
+
+impl OperationStream {
+    /// `keys` are the keys in the tree, `workload` gives the new keys and values
+    pub fn new(profile: WorkloadProfile, keys: Vec<Hash>, workload: Workload) -> OperationStream {
+        let zipf = match profile.distribution {
+            KeyDistribution::Zipfian(exponent) => {
+                Some(Zipf::new(keys.len().max(1) as u64, exponent).expect("valid zipfian exponent"))
+            }
+            _ => None,
+        };
+        OperationStream {
+            profile,
+            keys,
+            workload,
+            cursor: 0,
+            zipf,
+        }
+    }
+
+    pub fn next_operation(&mut self) -> Operation {
+        let WorkloadProfile {
+            read,
+            update,
+            insert,
+            delete,
+            ..
+        } = self.profile;
+        let mut pick = self
+            .workload
+            .rng()
+            .gen_range(0..read + update + insert + delete);
+        // Reads, updates and deletes need a key in the tree
+        if self.keys.is_empty() {
+            pick = read + update;
+        }
+
+        if pick < read {
+            let index = self.key_index();
+            return Operation::Read(self.keys[index]);
+        }
+        if pick < read + update {
+            let index = self.key_index();
+            let key = self.keys[index];
+            return Operation::Update(key, self.workload.hash());
+        }
+        if pick < read + update + insert || self.keys.is_empty() {
+            let (key, value) = self.workload.entry();
+            self.keys.push(key);
+            return Operation::Insert(key, value);
+        }
+        let index = self.key_index();
+        // Shifting the next keys back keeps them in insertion order, the cursor follows them
+        if index < self.cursor {
+            self.cursor -= 1;
+        }
+        Operation::Delete(self.keys.remove(index))
+    }
+
+    fn key_index(&mut self) -> usize {
+        let len = self.keys.len();
+        match self.profile.distribution {
+            KeyDistribution::Uniform => self.workload.rng().gen_range(0..len),
+            KeyDistribution::Zipfian(_) => {
+                let zipf = self
+                    .zipf
+                    .as_ref()
+                    .expect("zipf built for a zipfian profile");
+                (zipf.sample(self.workload.rng()) as usize - 1).min(len - 1)
+            }
+            KeyDistribution::HotSet {
+                fraction,
+                probability,
+            } => {
+                let hot = ((len as f64 * fraction) as usize).clamp(1, len);
+                if hot == len || self.workload.rng().gen_bool(probability) {
+                    self.workload.rng().gen_range(0..hot)
+                } else {
+                    self.workload.rng().gen_range(hot..len)
+                }
+            }
+            KeyDistribution::Sequential => {
+                let index = self.cursor % len;
+                self.cursor = index + 1;
+                index
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use monotree::Hash;
+
+    use super::{KeyDistribution, Operation, OperationStream, WorkloadProfile};
+    use crate::workload::Workload;
+
+    #[test]
+    fn operations_only_touch_keys_in_the_tree() {
+        for profile in WorkloadProfile::all() {
+            let mut workload = Workload::new(1);
+            let mut keys: Vec<_> = workload.entries(50).into_iter().map(|(k, _)| k).collect();
+            let mut stream = OperationStream::new(profile.clone(), keys.clone(), workload);
+            for _ in 0..1000 {
+                match stream.next_operation() {
+                    Operation::Read(key) | Operation::Update(key, _) => {
+                        assert!(keys.contains(&key), "{}", profile.name)
+                    }
+                    Operation::Insert(key, _) => keys.push(key),
+                    Operation::Delete(key) => {
+                        let index = keys.iter().position(|k| *k == key).unwrap();
+                        keys.remove(index);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn hot_set_gets_most_accesses() {
+        let profile = WorkloadProfile {
+            name: "hot",
+            read: 1,
+            update: 0,
+            insert: 0,
+            delete: 0,
+            distribution: KeyDistribution::HotSet {
+                fraction: 0.1,
+                probability: 0.9,
+            },
+        };
+        let mut workload = Workload::new(2);
+        let keys: Vec<_> = workload.entries(100).into_iter().map(|(k, _)| k).collect();
+        let hot = &keys[..10];
+        let mut stream = OperationStream::new(profile, keys.clone(), workload);
+        let hits = (0..10_000)
+            .filter(
+                |_| matches!(stream.next_operation(), Operation::Read(key) if hot.contains(&key)),
+            )
+            .count();
+        assert!((8_500..9_500).contains(&hits), "{} hot hits", hits);
+    }
+
+    fn sequential(read: u32, delete: u32) -> WorkloadProfile {
+        WorkloadProfile {
+            name: "sequential",
+            read,
+            update: 0,
+            insert: 0,
+            delete,
+            distribution: KeyDistribution::Sequential,
+        }
+    }
+
+    fn key_of(operation: Operation) -> Hash {
+        match operation {
+            Operation::Read(key)
+            | Operation::Update(key, _)
+            | Operation::Insert(key, _)
+            | Operation::Delete(key) => key,
+        }
+    }
+
+    #[test]
+    fn sequential_deletes_follow_insertion_order() {
+        let mut workload = Workload::new(3);
+        let keys: Vec<_> = workload.entries(20).into_iter().map(|(k, _)| k).collect();
+        let mut stream = OperationStream::new(sequential(0, 1), keys.clone(), workload);
+        let deleted: Vec<_> = (0..20).map(|_| key_of(stream.next_operation())).collect();
+        assert_eq!(deleted, keys);
+    }
+
+    #[test]
+    fn sequential_visits_the_remaining_keys_in_turn() {
+        let mut workload = Workload::new(4);
+        let keys: Vec<_> = workload.entries(20).into_iter().map(|(k, _)| k).collect();
+        let mut stream = OperationStream::new(sequential(1, 1), keys.clone(), workload);
+        let mut remaining = Vec::new();
+        for key in keys.iter() {
+            match stream.next_operation() {
+                Operation::Read(read) => {
+                    assert_eq!(read, *key);
+                    remaining.push(read);
+                }
+                operation => assert_eq!(key_of(operation), *key),
+            }
+        }
+        // The second round starts over on the keys the first one did not delete
+        let second: Vec<_> = remaining
+            .iter()
+            .map(|_| key_of(stream.next_operation()))
+            .collect();
+        assert_eq!(second, remaining);
+    }
+}
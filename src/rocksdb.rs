@@ -1,126 +1,284 @@
-use lsmtree::{bytes::Bytes, BadProof};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+use lsmtree::bytes::Bytes;
 use monotree::Database;
-use rand::Rng;
 use sparse_merkle_tree::{
-    merge::MergeValue,
     traits::{StoreReadOps, StoreWriteOps},
     BranchKey, BranchNode, H256,
 };
 
+use crate::bench_dir::BenchDir;
 use crate::blake3::Blake3SmtHasher;
+use crate::branch_codec::{decode_branch, encode_branch, encode_branch_key, BRANCH_KEY_LEN};
+use crate::checkpoint::{CheckpointStore, HeldCheckpoints};
+use crate::error::{stored_hash, StoreError};
+use crate::metrics::StoreMetrics;
+use crate::rocksdb_config::active_config;
 
-pub struct SmtRockSdb {
+/// The hasher is only used by lsmtree, that takes it from the store
+pub struct SmtRockSdb<H = Blake3SmtHasher> {
     db: rocksdb::DB,
     branch_db: rocksdb::DB,
+    batch: Option<PendingBatch>,
+    checkpoints: HeldCheckpoints,
+    write_opts: rocksdb::WriteOptions,
+    hasher: PhantomData<H>,
+    metrics: Rc<StoreMetrics>,
+    // Holds the RocksDB statistics, enabled when the metrics are recorded
+    opts: rocksdb::Options,
+    // Dropped after the databases, once they are closed
+    dir: Option<BenchDir>,
+}
+
+/// Writes between `init_batch` and `finish_batch`, one batch for each database
+///
+/// A `WriteBatch` cannot be read, so the writes are also kept in a map,
+/// a `None` value being a pending delete.
+#[derive(Default)]
+struct PendingBatch {
+    batch: rocksdb::WriteBatch,
+    writes: HashMap<Vec<u8>, Option<Vec<u8>>>,
+    branch_batch: rocksdb::WriteBatch,
+    branch_writes: HashMap<Vec<u8>, Option<Vec<u8>>>,
 }
 
-impl Default for SmtRockSdb {
+/// Database of a `SmtRockSdb`, the branches of sparse-merkle-tree having their own
+#[derive(Clone, Copy)]
+enum Db {
+    Leaves,
+    Branches,
+}
+
+// A fresh directory for each store, deleted when the store is dropped
+impl<H> Default for SmtRockSdb<H> {
     fn default() -> Self {
-        let mut rng = rand::thread_rng();
-        let rand_nb: u64 = rng.gen();
-        SmtRockSdb::new(format!(".bench_db/rocksdb_default_{}", rand_nb).as_str())
+        SmtRockSdb::open_in(BenchDir::new("rocksdb"))
     }
 }
 
-// MONOTREE
+// Copies the databases in a fresh directory, RocksDB hard links the table files when it can
+impl<H> Clone for SmtRockSdb<H> {
+    fn clone(&self) -> Self {
+        let dir = BenchDir::new("rocksdb");
+        self.copy_to(&dir);
+        SmtRockSdb::open_in(dir)
+    }
+}
 
-impl Database for SmtRockSdb {
-    fn new(dbpath: &str) -> Self {
-        SmtRockSdb {
-            db: rocksdb::DB::open_default(dbpath).unwrap(),
-            branch_db: rocksdb::DB::open_default(format!("{}_-branch", dbpath)).unwrap(),
-        }
+impl<H> SmtRockSdb<H> {
+    /// Store whose databases live in `dir`, deleted with the store
+    fn open_in(dir: BenchDir) -> Self {
+        let mut store = SmtRockSdb::new(&dir.db_path("db"));
+        store.dir = Some(dir);
+        store
     }
 
-    fn get(&mut self, key: &[u8]) -> monotree::Result<Option<Vec<u8>>> {
-        Ok(self.db.get(key).unwrap())
+    fn copy_to(&self, dir: &BenchDir) {
+        assert!(
+            self.batch.is_none(),
+            "cannot copy a store with a pending batch"
+        );
+        let dbpath = dir.db_path("db");
+        checkpoint(&self.db, &dbpath);
+        checkpoint(&self.branch_db, &branch_db_path(&dbpath));
     }
 
-    fn put(&mut self, key: &[u8], value: Vec<u8>) -> monotree::Result<()> {
-        self.db.put(key, value).unwrap();
-        Ok(())
+    fn read(&self, db: Db, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+        if let Some(pending) = self.batch.as_ref() {
+            let writes = match db {
+                Db::Leaves => &pending.writes,
+                Db::Branches => &pending.branch_writes,
+            };
+            if let Some(value) = writes.get(key) {
+                return Ok(value.clone());
+            }
+        }
+        let db = match db {
+            Db::Leaves => &self.db,
+            Db::Branches => &self.branch_db,
+        };
+        Ok(db.get(key)?)
     }
 
-    fn delete(&mut self, key: &[u8]) -> monotree::Result<()> {
-        self.db.delete(key).unwrap();
+    /// Writes `value`, or deletes the key if there is none
+    fn write(&mut self, db: Db, key: &[u8], value: Option<&[u8]>) -> Result<(), StoreError> {
+        if let Some(pending) = self.batch.as_mut() {
+            let (batch, writes) = match db {
+                Db::Leaves => (&mut pending.batch, &mut pending.writes),
+                Db::Branches => (&mut pending.branch_batch, &mut pending.branch_writes),
+            };
+            match value {
+                Some(value) => batch.put(key, value),
+                None => batch.delete(key),
+            }
+            writes.insert(key.to_vec(), value.map(|v| v.to_vec()));
+            return Ok(());
+        }
+        let db = match db {
+            Db::Leaves => &self.db,
+            Db::Branches => &self.branch_db,
+        };
+        match value {
+            Some(value) => db.put_opt(key, value, &self.write_opts)?,
+            None => db.delete_opt(key, &self.write_opts)?,
+        }
         Ok(())
     }
+}
 
-    fn init_batch(&mut self) -> monotree::Result<()> {
-        Ok(())
+/// Copy of the databases a `SmtRockSdb` goes back to when rolled back
+pub struct RocksDbCheckpoint {
+    id: u64,
+    dir: BenchDir,
+}
+
+// RocksDB checkpoints, a rollback reopens the databases on the copy instead of undoing the writes
+impl<H> CheckpointStore for SmtRockSdb<H> {
+    type Checkpoint = RocksDbCheckpoint;
+
+    fn checkpoint(&mut self) -> Result<RocksDbCheckpoint, StoreError> {
+        if self.batch.is_some() {
+            return Err(StoreError::PendingBatch);
+        }
+        let dir = BenchDir::new("rocksdb_checkpoint");
+        self.copy_to(&dir);
+        Ok(RocksDbCheckpoint {
+            id: self.checkpoints.take(),
+            dir,
+        })
     }
 
-    fn finish_batch(&mut self) -> monotree::Result<()> {
+    // The store keeps its options and metrics, the databases it leaves are closed before
+    // their directory is removed
+    fn rollback(&mut self, checkpoint: RocksDbCheckpoint) -> Result<(), StoreError> {
+        if self.batch.is_some() {
+            return Err(StoreError::PendingBatch);
+        }
+        self.checkpoints.drop_from(checkpoint.id)?;
+        let dbpath = checkpoint.dir.db_path("db");
+        let db = rocksdb::DB::open(&self.opts, &dbpath)?;
+        let branch_db = rocksdb::DB::open(&self.opts, branch_db_path(&dbpath))?;
+        self.db = db;
+        self.branch_db = branch_db;
+        self.dir = Some(checkpoint.dir);
         Ok(())
     }
+
+    fn release(&mut self, checkpoint: RocksDbCheckpoint) -> Result<(), StoreError> {
+        self.checkpoints.drop_from(checkpoint.id)
+    }
 }
 
-// SPARSE MERKLE TREE
+pub(crate) fn checkpoint(db: &rocksdb::DB, path: &str) {
+    rocksdb::checkpoint::Checkpoint::new(db)
+        .and_then(|checkpoint| checkpoint.create_checkpoint(path))
+        .unwrap_or_else(|err| panic!("cannot copy the RocksDB database to {}: {}", path, err));
+}
+
+fn branch_db_path(dbpath: &str) -> String {
+    format!("{}_-branch", dbpath)
+}
 
-fn serialize_smt_branchnode_arm(arm: MergeValue, vec: &mut Vec<u8>) {
-    match arm {
-        MergeValue::Value(hash) => {
-            vec.push(1);
-            vec.extend(hash.as_slice().to_vec());
+// The table files are measured once the memtables are flushed to them
+impl<H> Drop for SmtRockSdb<H> {
+    fn drop(&mut self) {
+        if !self.metrics.is_recording() {
+            return;
         }
-        MergeValue::MergeWithZero {
-            base_node,
-            zero_bits,
-            zero_count,
-        } => {
-            vec.push(2);
-            vec.extend(base_node.as_slice().to_vec());
-            vec.extend(zero_bits.as_slice().to_vec());
-            vec.push(zero_count);
+        let (mut entries, mut stored_bytes, mut sst_bytes) = (0, 0, 0);
+        for db in [&self.db, &self.branch_db] {
+            for (key, value) in db.iterator(rocksdb::IteratorMode::Start).flatten() {
+                entries += 1;
+                stored_bytes += (key.len() + value.len()) as u64;
+            }
+            if db.flush().is_ok() {
+                sst_bytes += db
+                    .property_int_value("rocksdb.total-sst-files-size")
+                    .ok()
+                    .flatten()
+                    .unwrap_or(0);
+            }
         }
+        self.metrics.add_footprint(entries, stored_bytes);
+        self.metrics
+            .add_rocksdb_footprint(sst_bytes, self.opts.get_statistics());
     }
 }
 
-fn get_32_bytes<'a, I: Iterator<Item = &'a u8>>(bytes: &mut I) -> [u8; 32] {
-    let data = bytes.take(32).cloned().collect::<Vec<u8>>();
-    debug_assert_eq!(data.len(), 32);
-    data.try_into().unwrap()
-}
+// MONOTREE
 
-fn merge_value_from_bytes<'a, I: Iterator<Item = &'a u8>>(bytes: &mut I) -> Result<MergeValue, ()> {
-    match *bytes.next().unwrap() {
-        1 => Ok(MergeValue::Value(H256::from(get_32_bytes(bytes)))),
-        2 => Ok(MergeValue::MergeWithZero {
-            base_node: H256::from(get_32_bytes(bytes)),
-            zero_bits: H256::from(get_32_bytes(bytes)),
-            zero_count: *bytes.next().unwrap(),
-        }),
-        _ => Err(()),
+impl<H> Database for SmtRockSdb<H> {
+    // The monotree API leaves no way to report a failure to open the database
+    fn new(dbpath: &str) -> Self {
+        let config = active_config();
+        let metrics = StoreMetrics::current();
+        let mut opts = config.options();
+        if metrics.is_recording() {
+            opts.enable_statistics();
+        }
+        SmtRockSdb {
+            db: rocksdb::DB::open(&opts, dbpath).expect("cannot open the RocksDB database"),
+            branch_db: rocksdb::DB::open(&opts, branch_db_path(dbpath))
+                .expect("cannot open the RocksDB branch database"),
+            batch: None,
+            checkpoints: HeldCheckpoints::default(),
+            write_opts: config.write_options(),
+            hasher: PhantomData,
+            metrics,
+            opts,
+            dir: None,
+        }
+    }
+
+    fn get(&mut self, key: &[u8]) -> monotree::Result<Option<Vec<u8>>> {
+        self.metrics.read();
+        Ok(self.read(Db::Leaves, key)?)
+    }
+
+    fn put(&mut self, key: &[u8], value: Vec<u8>) -> monotree::Result<()> {
+        self.metrics.write(key.len(), value.len());
+        Ok(self.write(Db::Leaves, key, Some(&value))?)
     }
-}
 
-fn deserialize_smt_branchnode_arm(data: Vec<u8>) -> Option<(MergeValue, MergeValue)> {
-    let mut bytes = data.iter();
-    let larm_res = merge_value_from_bytes(&mut bytes);
-    if larm_res.is_err() {
-        return None;
+    fn delete(&mut self, key: &[u8]) -> monotree::Result<()> {
+        self.metrics.delete();
+        Ok(self.write(Db::Leaves, key, None)?)
     }
-    let larm = larm_res.unwrap();
-    let rarm_res = merge_value_from_bytes(&mut bytes);
-    if rarm_res.is_err() {
-        return None;
+
+    // Starting a batch while one is pending keeps collecting into the pending one
+    fn init_batch(&mut self) -> monotree::Result<()> {
+        self.batch.get_or_insert_with(PendingBatch::default);
+        Ok(())
+    }
+
+    // The writes to each database are applied at once, a failure between the two writes
+    // leaves the leaves ahead of the branches, the layout `SmtRockSdbCf` avoids
+    fn finish_batch(&mut self) -> monotree::Result<()> {
+        if let Some(pending) = self.batch.take() {
+            self.db
+                .write_opt(pending.batch, &self.write_opts)
+                .map_err(StoreError::from)?;
+            self.branch_db
+                .write_opt(pending.branch_batch, &self.write_opts)
+                .map_err(StoreError::from)?;
+        }
+        Ok(())
     }
-    let rarm = rarm_res.unwrap();
-    Some((larm, rarm))
 }
 
-impl StoreWriteOps<H256> for SmtRockSdb {
+// SPARSE MERKLE TREE
+
+impl<H> StoreWriteOps<H256> for SmtRockSdb<H> {
     fn insert_branch(
         &mut self,
         node_key: BranchKey,
         branch: BranchNode,
     ) -> Result<(), sparse_merkle_tree::error::Error> {
-        let mut buffer = vec![];
-        serialize_smt_branchnode_arm(branch.left, &mut buffer);
-        serialize_smt_branchnode_arm(branch.right, &mut buffer);
-        self.branch_db
-            .put(node_key.node_key.as_slice(), buffer)
-            .unwrap();
+        let bytes = encode_branch(&branch);
+        self.metrics.write(BRANCH_KEY_LEN, bytes.len());
+        self.write(Db::Branches, &encode_branch_key(&node_key), Some(&bytes))?;
         Ok(())
     }
 
@@ -129,7 +287,8 @@ impl StoreWriteOps<H256> for SmtRockSdb {
         leaf_key: H256,
         leaf: H256,
     ) -> Result<(), sparse_merkle_tree::error::Error> {
-        self.db.put(leaf_key.as_slice(), leaf.as_slice()).unwrap();
+        self.metrics.write(32, 32);
+        self.write(Db::Leaves, leaf_key.as_slice(), Some(leaf.as_slice()))?;
         Ok(())
     }
 
@@ -137,53 +296,48 @@ impl StoreWriteOps<H256> for SmtRockSdb {
         &mut self,
         node_key: &BranchKey,
     ) -> Result<(), sparse_merkle_tree::error::Error> {
-        self.branch_db.delete(node_key.node_key.as_slice()).unwrap();
+        self.metrics.delete();
+        self.write(Db::Branches, &encode_branch_key(node_key), None)?;
         Ok(())
     }
 
     fn remove_leaf(&mut self, leaf_key: &H256) -> Result<(), sparse_merkle_tree::error::Error> {
-        self.db.delete(leaf_key.as_slice()).unwrap();
+        self.metrics.delete();
+        self.write(Db::Leaves, leaf_key.as_slice(), None)?;
         Ok(())
     }
 }
 
-impl StoreReadOps<H256> for SmtRockSdb {
+impl<H> StoreReadOps<H256> for SmtRockSdb<H> {
     fn get_branch(
         &self,
         branch_key: &BranchKey,
     ) -> Result<Option<BranchNode>, sparse_merkle_tree::error::Error> {
-        if let Some(data) = self.branch_db.get(branch_key.node_key.as_slice()).unwrap() {
-            if let Some((larm, rarm)) = deserialize_smt_branchnode_arm(data) {
-                let branchnode = BranchNode {
-                    left: larm,
-                    right: rarm,
-                };
-                Ok(Some(branchnode))
-            } else {
-                Ok(None)
-            }
-        } else {
-            Ok(None)
+        self.metrics.read();
+        match self.read(Db::Branches, &encode_branch_key(branch_key))? {
+            Some(data) => Ok(Some(decode_branch(&data)?)),
+            None => Ok(None),
         }
     }
 
     fn get_leaf(&self, leaf_key: &H256) -> Result<Option<H256>, sparse_merkle_tree::error::Error> {
-        let data = self.db.get(leaf_key.as_slice()).unwrap();
-        Ok(data.map(|d| {
-            let array: [u8; 32] = d.try_into().unwrap();
-            array.into()
-        }))
+        self.metrics.read();
+        match self.read(Db::Leaves, leaf_key.as_slice())? {
+            Some(data) => Ok(Some(stored_hash(&data)?.into())),
+            None => Ok(None),
+        }
     }
 }
 
 // LSMTREE
 
-impl lsmtree::KVStore for SmtRockSdb {
-    type Hasher = Blake3SmtHasher;
-    type Error = BadProof;
+impl<H: lsmtree::digest::Digest> lsmtree::KVStore for SmtRockSdb<H> {
+    type Hasher = H;
+    type Error = StoreError;
 
     fn get(&self, key: &[u8]) -> Result<Option<lsmtree::bytes::Bytes>, Self::Error> {
-        Ok(self.db.get(key).unwrap().map(Bytes::from))
+        self.metrics.read();
+        Ok(self.db.get(key)?.map(Bytes::from))
     }
 
     fn set(
@@ -191,17 +345,144 @@ impl lsmtree::KVStore for SmtRockSdb {
         key: lsmtree::bytes::Bytes,
         value: lsmtree::bytes::Bytes,
     ) -> Result<(), Self::Error> {
-        self.db.put(key.to_vec().as_slice(), &value).unwrap();
+        self.metrics.write(key.len(), value.len());
+        self.db
+            .put_opt(key.to_vec().as_slice(), &value, &self.write_opts)?;
         Ok(())
     }
 
     fn remove(&mut self, key: &[u8]) -> Result<lsmtree::bytes::Bytes, Self::Error> {
-        let content = Bytes::from(self.get(key).unwrap().unwrap());
-        self.db.delete(key).unwrap();
-        Ok(content)
+        self.metrics.read();
+        self.metrics.delete();
+        let content = self
+            .db
+            .get(key)?
+            .ok_or_else(|| StoreError::MissingKey(key.to_vec()))?;
+        self.db.delete_opt(key, &self.write_opts)?;
+        Ok(Bytes::from(content))
     }
 
+    // `key_may_exist` can say an absent key may exist, only a false answer is certain
     fn contains(&self, key: &[u8]) -> Result<bool, Self::Error> {
-        Ok(self.db.key_may_exist(key))
+        self.metrics.read();
+        Ok(self.db.key_may_exist(key) && self.db.get(key)?.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use monotree::Database;
+    use proptest::proptest;
+    use sparse_merkle_tree::traits::StoreReadOps;
+    use sparse_merkle_tree::{BranchKey, H256};
+
+    use super::SmtRockSdb;
+    use crate::blake3::Blake3SmtHasher;
+    use crate::branch_codec::encode_branch_key;
+    use crate::checkpoint::CheckpointStore;
+    use crate::error::StoreError;
+    use crate::metrics::StoreMetrics;
+    use crate::store_model::{
+        check_database, check_kv_store, check_smt_store, database_ops, kv_ops, smt_store_ops,
+    };
+
+    #[test]
+    fn batch_reads_its_own_writes_and_commits_on_finish() {
+        let mut store: SmtRockSdb = SmtRockSdb::default();
+        store.put(b"kept", vec![1]).unwrap();
+        store.put(b"deleted", vec![2]).unwrap();
+
+        store.init_batch().unwrap();
+        store.put(b"added", vec![3]).unwrap();
+        store.put(b"kept", vec![4]).unwrap();
+        store.delete(b"deleted").unwrap();
+
+        assert_eq!(store.get(b"added").unwrap(), Some(vec![3]));
+        assert_eq!(store.get(b"kept").unwrap(), Some(vec![4]));
+        assert_eq!(store.get(b"deleted").unwrap(), None);
+        assert_eq!(store.db.get(b"added").unwrap(), None);
+        assert_eq!(store.db.get(b"kept").unwrap(), Some(vec![1]));
+        assert_eq!(store.db.get(b"deleted").unwrap(), Some(vec![2]));
+
+        store.finish_batch().unwrap();
+        assert!(store.batch.is_none());
+        assert_eq!(store.db.get(b"added").unwrap(), Some(vec![3]));
+        assert_eq!(store.db.get(b"kept").unwrap(), Some(vec![4]));
+        assert_eq!(store.db.get(b"deleted").unwrap(), None);
+    }
+
+    // A branch that cannot be decoded must not look absent to the tree
+    #[test]
+    fn corrupted_branch_is_an_error() {
+        let store: SmtRockSdb = SmtRockSdb::default();
+        let branch_key = BranchKey::new(1, H256::from([1; 32]));
+        store
+            .branch_db
+            .put(encode_branch_key(&branch_key), [1, 2])
+            .unwrap();
+        assert!(store.get_branch(&branch_key).is_err());
+    }
+
+    #[test]
+    fn metrics_are_recorded_across_a_rollback() {
+        let (mut store, metrics) = StoreMetrics::record(SmtRockSdb::<Blake3SmtHasher>::default);
+        store.put(b"kept", vec![1]).unwrap();
+        let checkpoint = store.checkpoint().unwrap();
+        store.put(b"undone", vec![2]).unwrap();
+        store.rollback(checkpoint).unwrap();
+        assert_eq!(store.get(b"undone").unwrap(), None);
+        store.put(b"after", vec![3]).unwrap();
+
+        let counts = metrics.counts();
+        assert_eq!((counts.reads, counts.writes), (1, 3));
+        drop(store);
+        assert_eq!(metrics.footprint().entries, 2);
+    }
+
+    #[test]
+    fn rollback_during_a_batch_is_an_error() {
+        let mut store: SmtRockSdb = SmtRockSdb::default();
+        let checkpoint = store.checkpoint().unwrap();
+        store.init_batch().unwrap();
+        store.put(b"pending", vec![1]).unwrap();
+        assert!(matches!(store.checkpoint(), Err(StoreError::PendingBatch)));
+        assert!(matches!(
+            store.rollback(checkpoint),
+            Err(StoreError::PendingBatch)
+        ));
+
+        store.finish_batch().unwrap();
+        assert_eq!(store.get(b"pending").unwrap(), Some(vec![1]));
+    }
+
+    #[test]
+    fn clone_is_an_independent_copy() {
+        let mut store: SmtRockSdb = SmtRockSdb::default();
+        store.put(b"shared", vec![1]).unwrap();
+
+        let mut copy = store.clone();
+        copy.put(b"shared", vec![2]).unwrap();
+        copy.put(b"copy only", vec![3]).unwrap();
+
+        assert_eq!(store.get(b"shared").unwrap(), Some(vec![1]));
+        assert_eq!(store.get(b"copy only").unwrap(), None);
+        assert_eq!(copy.get(b"shared").unwrap(), Some(vec![2]));
+    }
+
+    proptest! {
+        #[test]
+        fn database_matches_model(ops in database_ops()) {
+            check_database(SmtRockSdb::<Blake3SmtHasher>::default(), ops)?;
+        }
+
+        #[test]
+        fn smt_store_matches_model(ops in smt_store_ops()) {
+            check_smt_store(SmtRockSdb::<Blake3SmtHasher>::default(), ops)?;
+        }
+
+        #[test]
+        fn kv_store_matches_model(ops in kv_ops()) {
+            check_kv_store(SmtRockSdb::<Blake3SmtHasher>::default(), ops)?;
+        }
     }
 }
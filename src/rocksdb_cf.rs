@@ -0,0 +1,436 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+use lsmtree::bytes::Bytes;
+use monotree::Database;
+use sparse_merkle_tree::{
+    traits::{StoreReadOps, StoreWriteOps},
+    BranchKey, BranchNode, H256,
+};
+
+use crate::bench_dir::BenchDir;
+use crate::blake3::Blake3SmtHasher;
+use crate::branch_codec::{decode_branch, encode_branch, encode_branch_key};
+use crate::checkpoint::{CheckpointStore, HeldCheckpoints};
+use crate::error::{stored_hash, StoreError};
+use crate::metrics::StoreMetrics;
+use crate::rocksdb::checkpoint;
+use crate::rocksdb_config::active_config;
+
+const LEAVES_CF: &str = "leaves";
+const BRANCHES_CF: &str = "branches";
+
+/// Same content as `SmtRockSdb`, with leaves and branches in two column families of one DB
+///
+/// Both share the WAL, and a batch spanning both is committed in one write.
+pub struct SmtRockSdbCf<H = Blake3SmtHasher> {
+    db: rocksdb::DB,
+    batch: Option<PendingBatch>,
+    checkpoints: HeldCheckpoints,
+    write_opts: rocksdb::WriteOptions,
+    hasher: PhantomData<H>,
+    metrics: Rc<StoreMetrics>,
+    // Holds the RocksDB statistics, enabled when the metrics are recorded
+    opts: rocksdb::Options,
+    // Dropped after the databases, once they are closed
+    dir: Option<BenchDir>,
+}
+
+/// Writes between `init_batch` and `finish_batch`, readable through the map
+#[derive(Default)]
+struct PendingBatch {
+    batch: rocksdb::WriteBatch,
+    writes: HashMap<(&'static str, Vec<u8>), Option<Vec<u8>>>,
+}
+
+// A fresh directory for each store, deleted when the store is dropped
+impl<H> Default for SmtRockSdbCf<H> {
+    fn default() -> Self {
+        SmtRockSdbCf::open_in(BenchDir::new("rocksdb_cf"))
+    }
+}
+
+// Copies the database in a fresh directory, RocksDB hard links the table files when it can
+impl<H> Clone for SmtRockSdbCf<H> {
+    fn clone(&self) -> Self {
+        let dir = BenchDir::new("rocksdb_cf");
+        self.copy_to(&dir);
+        SmtRockSdbCf::open_in(dir)
+    }
+}
+
+/// Copy of the database a `SmtRockSdbCf` goes back to when rolled back
+pub struct RocksDbCfCheckpoint {
+    id: u64,
+    dir: BenchDir,
+}
+
+// Same as `SmtRockSdb`, one checkpoint holds both column families
+impl<H> CheckpointStore for SmtRockSdbCf<H> {
+    type Checkpoint = RocksDbCfCheckpoint;
+
+    fn checkpoint(&mut self) -> Result<RocksDbCfCheckpoint, StoreError> {
+        if self.batch.is_some() {
+            return Err(StoreError::PendingBatch);
+        }
+        let dir = BenchDir::new("rocksdb_cf_checkpoint");
+        self.copy_to(&dir);
+        Ok(RocksDbCfCheckpoint {
+            id: self.checkpoints.take(),
+            dir,
+        })
+    }
+
+    fn rollback(&mut self, checkpoint: RocksDbCfCheckpoint) -> Result<(), StoreError> {
+        if self.batch.is_some() {
+            return Err(StoreError::PendingBatch);
+        }
+        self.checkpoints.drop_from(checkpoint.id)?;
+        self.db = rocksdb::DB::open_cf(
+            &self.opts,
+            checkpoint.dir.db_path("db"),
+            [LEAVES_CF, BRANCHES_CF],
+        )?;
+        self.dir = Some(checkpoint.dir);
+        Ok(())
+    }
+
+    fn release(&mut self, checkpoint: RocksDbCfCheckpoint) -> Result<(), StoreError> {
+        self.checkpoints.drop_from(checkpoint.id)
+    }
+}
+
+impl<H> SmtRockSdbCf<H> {
+    fn open_in(dir: BenchDir) -> Self {
+        let mut store = SmtRockSdbCf::new(&dir.db_path("db"));
+        store.dir = Some(dir);
+        store
+    }
+
+    fn copy_to(&self, dir: &BenchDir) {
+        assert!(
+            self.batch.is_none(),
+            "cannot copy a store with a pending batch"
+        );
+        checkpoint(&self.db, &dir.db_path("db"));
+    }
+
+    fn read(&self, cf: &'static str, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+        self.metrics.read();
+        if let Some(pending) = self.batch.as_ref() {
+            if let Some(value) = pending.writes.get(&(cf, key.to_vec())) {
+                return Ok(value.clone());
+            }
+        }
+        Ok(self.db.get_cf(self.cf(cf), key)?)
+    }
+
+    /// Writes `value`, or deletes the key if there is none
+    fn write(
+        &mut self,
+        cf: &'static str,
+        key: &[u8],
+        value: Option<&[u8]>,
+    ) -> Result<(), StoreError> {
+        match value {
+            Some(value) => self.metrics.write(key.len(), value.len()),
+            None => self.metrics.delete(),
+        }
+        if let Some(mut pending) = self.batch.take() {
+            match value {
+                Some(value) => pending.batch.put_cf(self.cf(cf), key, value),
+                None => pending.batch.delete_cf(self.cf(cf), key),
+            }
+            pending
+                .writes
+                .insert((cf, key.to_vec()), value.map(|v| v.to_vec()));
+            self.batch = Some(pending);
+            return Ok(());
+        }
+        match value {
+            Some(value) => self
+                .db
+                .put_cf_opt(self.cf(cf), key, value, &self.write_opts)?,
+            None => self.db.delete_cf_opt(self.cf(cf), key, &self.write_opts)?,
+        }
+        Ok(())
+    }
+
+    fn cf(&self, name: &str) -> &rocksdb::ColumnFamily {
+        self.db
+            .cf_handle(name)
+            .expect("column family created on open")
+    }
+}
+
+// Same as `SmtRockSdb`, the column families measured one after the other
+impl<H> Drop for SmtRockSdbCf<H> {
+    fn drop(&mut self) {
+        if !self.metrics.is_recording() {
+            return;
+        }
+        let (mut entries, mut stored_bytes, mut sst_bytes) = (0, 0, 0);
+        for cf in [LEAVES_CF, BRANCHES_CF] {
+            let cf = self.cf(cf);
+            for (key, value) in self
+                .db
+                .iterator_cf(cf, rocksdb::IteratorMode::Start)
+                .flatten()
+            {
+                entries += 1;
+                stored_bytes += (key.len() + value.len()) as u64;
+            }
+            if self.db.flush_cf(cf).is_ok() {
+                sst_bytes += self
+                    .db
+                    .property_int_value_cf(cf, "rocksdb.total-sst-files-size")
+                    .ok()
+                    .flatten()
+                    .unwrap_or(0);
+            }
+        }
+        self.metrics.add_footprint(entries, stored_bytes);
+        self.metrics
+            .add_rocksdb_footprint(sst_bytes, self.opts.get_statistics());
+    }
+}
+
+// MONOTREE
+
+impl<H> Database for SmtRockSdbCf<H> {
+    // The monotree API leaves no way to report a failure to open the database
+    fn new(dbpath: &str) -> Self {
+        let config = active_config();
+        let metrics = StoreMetrics::current();
+        let mut opts = config.options();
+        opts.create_missing_column_families(true);
+        if metrics.is_recording() {
+            opts.enable_statistics();
+        }
+        SmtRockSdbCf {
+            db: rocksdb::DB::open_cf(&opts, dbpath, [LEAVES_CF, BRANCHES_CF])
+                .expect("cannot open the RocksDB database"),
+            batch: None,
+            checkpoints: HeldCheckpoints::default(),
+            write_opts: config.write_options(),
+            hasher: PhantomData,
+            metrics,
+            opts,
+            dir: None,
+        }
+    }
+
+    fn get(&mut self, key: &[u8]) -> monotree::Result<Option<Vec<u8>>> {
+        Ok(self.read(LEAVES_CF, key)?)
+    }
+
+    fn put(&mut self, key: &[u8], value: Vec<u8>) -> monotree::Result<()> {
+        Ok(self.write(LEAVES_CF, key, Some(&value))?)
+    }
+
+    fn delete(&mut self, key: &[u8]) -> monotree::Result<()> {
+        Ok(self.write(LEAVES_CF, key, None)?)
+    }
+
+    fn init_batch(&mut self) -> monotree::Result<()> {
+        self.batch.get_or_insert_with(PendingBatch::default);
+        Ok(())
+    }
+
+    fn finish_batch(&mut self) -> monotree::Result<()> {
+        if let Some(pending) = self.batch.take() {
+            self.db
+                .write_opt(pending.batch, &self.write_opts)
+                .map_err(StoreError::from)?;
+        }
+        Ok(())
+    }
+}
+
+// SPARSE MERKLE TREE
+
+impl<H> StoreWriteOps<H256> for SmtRockSdbCf<H> {
+    fn insert_branch(
+        &mut self,
+        node_key: BranchKey,
+        branch: BranchNode,
+    ) -> Result<(), sparse_merkle_tree::error::Error> {
+        let bytes = encode_branch(&branch);
+        self.write(BRANCHES_CF, &encode_branch_key(&node_key), Some(&bytes))?;
+        Ok(())
+    }
+
+    fn insert_leaf(
+        &mut self,
+        leaf_key: H256,
+        leaf: H256,
+    ) -> Result<(), sparse_merkle_tree::error::Error> {
+        self.write(LEAVES_CF, leaf_key.as_slice(), Some(leaf.as_slice()))?;
+        Ok(())
+    }
+
+    fn remove_branch(
+        &mut self,
+        node_key: &BranchKey,
+    ) -> Result<(), sparse_merkle_tree::error::Error> {
+        self.write(BRANCHES_CF, &encode_branch_key(node_key), None)?;
+        Ok(())
+    }
+
+    fn remove_leaf(&mut self, leaf_key: &H256) -> Result<(), sparse_merkle_tree::error::Error> {
+        self.write(LEAVES_CF, leaf_key.as_slice(), None)?;
+        Ok(())
+    }
+}
+
+impl<H> StoreReadOps<H256> for SmtRockSdbCf<H> {
+    fn get_branch(
+        &self,
+        branch_key: &BranchKey,
+    ) -> Result<Option<BranchNode>, sparse_merkle_tree::error::Error> {
+        match self.read(BRANCHES_CF, &encode_branch_key(branch_key))? {
+            Some(data) => Ok(Some(decode_branch(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn get_leaf(&self, leaf_key: &H256) -> Result<Option<H256>, sparse_merkle_tree::error::Error> {
+        match self.read(LEAVES_CF, leaf_key.as_slice())? {
+            Some(data) => Ok(Some(stored_hash(&data)?.into())),
+            None => Ok(None),
+        }
+    }
+}
+
+// LSMTREE
+
+impl<H: lsmtree::digest::Digest> lsmtree::KVStore for SmtRockSdbCf<H> {
+    type Hasher = H;
+    type Error = StoreError;
+
+    fn get(&self, key: &[u8]) -> Result<Option<lsmtree::bytes::Bytes>, Self::Error> {
+        Ok(self.read(LEAVES_CF, key)?.map(Bytes::from))
+    }
+
+    fn set(
+        &mut self,
+        key: lsmtree::bytes::Bytes,
+        value: lsmtree::bytes::Bytes,
+    ) -> Result<(), Self::Error> {
+        self.write(LEAVES_CF, &key, Some(&value))
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Result<lsmtree::bytes::Bytes, Self::Error> {
+        let content = self
+            .read(LEAVES_CF, key)?
+            .ok_or_else(|| StoreError::MissingKey(key.to_vec()))?;
+        self.write(LEAVES_CF, key, None)?;
+        Ok(Bytes::from(content))
+    }
+
+    fn contains(&self, key: &[u8]) -> Result<bool, Self::Error> {
+        Ok(self.read(LEAVES_CF, key)?.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use monotree::Database;
+    use proptest::proptest;
+    use sparse_merkle_tree::traits::{StoreReadOps, StoreWriteOps};
+    use sparse_merkle_tree::{merge::MergeValue, BranchKey, BranchNode, H256};
+
+    use super::{SmtRockSdbCf, BRANCHES_CF, LEAVES_CF};
+    use crate::blake3::Blake3SmtHasher;
+    use crate::branch_codec::encode_branch_key;
+    use crate::checkpoint::CheckpointStore;
+    use crate::metrics::StoreMetrics;
+    use crate::store_model::{
+        check_database, check_kv_store, check_smt_store, database_ops, kv_ops, smt_store_ops,
+    };
+
+    #[test]
+    fn batch_spans_both_column_families() {
+        let mut store: SmtRockSdbCf = SmtRockSdbCf::default();
+        let branch_key = BranchKey::new(3, H256::from([1; 32]));
+        let branch = BranchNode {
+            left: MergeValue::Value(H256::from([2; 32])),
+            right: MergeValue::zero(),
+        };
+
+        store.init_batch().unwrap();
+        store.insert_leaf([4; 32].into(), [5; 32].into()).unwrap();
+        store
+            .insert_branch(branch_key.clone(), branch.clone())
+            .unwrap();
+        assert_eq!(
+            store.get_leaf(&[4; 32].into()).unwrap(),
+            Some([5; 32].into())
+        );
+        assert_eq!(store.get_branch(&branch_key).unwrap(), Some(branch.clone()));
+        let stored_branch = store
+            .db
+            .get_cf(store.cf(BRANCHES_CF), encode_branch_key(&branch_key));
+        assert_eq!(stored_branch.unwrap(), None);
+        assert_eq!(store.db.get_cf(store.cf(LEAVES_CF), [4; 32]).unwrap(), None);
+
+        store.finish_batch().unwrap();
+        assert!(store.batch.is_none());
+        assert_eq!(
+            store.get_leaf(&[4; 32].into()).unwrap(),
+            Some([5; 32].into())
+        );
+        assert_eq!(store.get_branch(&branch_key).unwrap(), Some(branch));
+    }
+
+    // Same counts and footprint as `SmtRockSdb` for the same writes
+    #[test]
+    fn writes_and_footprint_are_recorded() {
+        let (mut store, metrics) = StoreMetrics::record(SmtRockSdbCf::<Blake3SmtHasher>::default);
+        store.put(&[1; 32], vec![2; 32]).unwrap();
+        store.insert_leaf([3; 32].into(), [4; 32].into()).unwrap();
+        store.remove_leaf(&[3; 32].into()).unwrap();
+        assert_eq!(store.get(&[1; 32]).unwrap(), Some(vec![2; 32]));
+
+        let counts = metrics.counts();
+        assert_eq!((counts.reads, counts.writes, counts.deletes), (1, 2, 1));
+        assert_eq!(counts.bytes_written, 128);
+        drop(store);
+        let footprint = metrics.footprint();
+        assert_eq!((footprint.entries, footprint.stored_bytes), (1, 64));
+    }
+
+    #[test]
+    fn metrics_are_recorded_across_a_rollback() {
+        let (mut store, metrics) = StoreMetrics::record(SmtRockSdbCf::<Blake3SmtHasher>::default);
+        store.put(&[1; 32], vec![1]).unwrap();
+        let checkpoint = store.checkpoint().unwrap();
+        store.insert_leaf([2; 32].into(), [2; 32].into()).unwrap();
+        store.rollback(checkpoint).unwrap();
+        assert_eq!(store.get_leaf(&[2; 32].into()).unwrap(), None);
+        store.put(&[3; 32], vec![3]).unwrap();
+
+        let counts = metrics.counts();
+        assert_eq!((counts.reads, counts.writes), (1, 3));
+        drop(store);
+        assert_eq!(metrics.footprint().entries, 2);
+    }
+
+    proptest! {
+        #[test]
+        fn database_matches_model(ops in database_ops()) {
+            check_database(SmtRockSdbCf::<Blake3SmtHasher>::default(), ops)?;
+        }
+
+        #[test]
+        fn smt_store_matches_model(ops in smt_store_ops()) {
+            check_smt_store(SmtRockSdbCf::<Blake3SmtHasher>::default(), ops)?;
+        }
+
+        #[test]
+        fn kv_store_matches_model(ops in kv_ops()) {
+            check_kv_store(SmtRockSdbCf::<Blake3SmtHasher>::default(), ops)?;
+        }
+    }
+}
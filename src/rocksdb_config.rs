@@ -0,0 +1,148 @@
+use std::sync::OnceLock;
+
+use rocksdb::{BlockBasedOptions, Cache, DBCompressionType, Options, SliceTransform, WriteOptions};
+
+/// Environment variable selecting the RocksDB profile of a benchmark run
+pub const PROFILE_ENV: &str = "SMT_BENCH_ROCKSDB_PROFILE";
+
+pub const PROFILES: [&str; 4] = ["default", "read-optimized", "write-optimized", "direct-io"];
+
+// Monotree opens its database from a path only, so the stores read the profile from here
+static ACTIVE_CONFIG: OnceLock<RocksDbConfig> = OnceLock::new();
+
+/// RocksDB tuning of the stores, a `None` keeps the RocksDB default
+#[derive(Debug, Clone)]
+pub struct RocksDbConfig {
+    pub name: String,
+    pub block_cache_size: Option<usize>,
+    pub bloom_filter_bits: Option<f64>,
+    pub compression: Option<DBCompressionType>,
+    pub write_buffer_size: Option<usize>,
+    pub disable_wal: bool,
+    pub direct_io: bool,
+    pub prefix_len: Option<usize>,
+}
+
+impl Default for RocksDbConfig {
+    fn default() -> Self {
+        RocksDbConfig {
+            name: "default".to_string(),
+            block_cache_size: None,
+            bloom_filter_bits: None,
+            compression: None,
+            write_buffer_size: None,
+            disable_wal: false,
+            direct_io: false,
+            prefix_len: None,
+        }
+    }
+}
+
+impl RocksDbConfig {
+    /// One of the named `PROFILES`
+    pub fn profile(name: &str) -> Option<RocksDbConfig> {
+        let config = match name {
+            "default" => RocksDbConfig::default(),
+            // The keys are hashes, the 8 bytes prefix blooms let the gets skip most table files
+            "read-optimized" => RocksDbConfig {
+                block_cache_size: Some(512 << 20),
+                bloom_filter_bits: Some(10.0),
+                compression: Some(DBCompressionType::None),
+                prefix_len: Some(8),
+                ..RocksDbConfig::default()
+            },
+            "write-optimized" => RocksDbConfig {
+                compression: Some(DBCompressionType::Lz4),
+                write_buffer_size: Some(256 << 20),
+                disable_wal: true,
+                ..RocksDbConfig::default()
+            },
+            // Bypasses the OS page cache, the block cache is all there is; needs a filesystem
+            // supporting O_DIRECT, which tmpfs is not
+            "direct-io" => RocksDbConfig {
+                block_cache_size: Some(512 << 20),
+                direct_io: true,
+                ..RocksDbConfig::default()
+            },
+            _ => return None,
+        };
+        Some(RocksDbConfig {
+            name: name.to_string(),
+            ..config
+        })
+    }
+
+    /// Profile named by `PROFILE_ENV`, "default" when it is not set
+    pub fn from_env() -> RocksDbConfig {
+        let name = std::env::var(PROFILE_ENV).unwrap_or_else(|_| "default".to_string());
+        RocksDbConfig::profile(&name).unwrap_or_else(|| {
+            panic!(
+                "unknown RocksDB profile {:?} in {}, expected one of {:?}",
+                name, PROFILE_ENV, PROFILES
+            )
+        })
+    }
+
+    pub fn options(&self) -> Options {
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+
+        let mut table = BlockBasedOptions::default();
+        if let Some(size) = self.block_cache_size {
+            table.set_block_cache(
+                &Cache::new_lru_cache(size).expect("cannot create the block cache"),
+            );
+        }
+        if let Some(bits) = self.bloom_filter_bits {
+            table.set_bloom_filter(bits, false);
+        }
+        opts.set_block_based_table_factory(&table);
+
+        if let Some(compression) = self.compression {
+            opts.set_compression_type(compression);
+        }
+        if let Some(size) = self.write_buffer_size {
+            opts.set_write_buffer_size(size);
+        }
+        if self.direct_io {
+            opts.set_use_direct_reads(true);
+            opts.set_use_direct_io_for_flush_and_compaction(true);
+        }
+        if let Some(len) = self.prefix_len {
+            opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(len));
+        }
+        opts
+    }
+
+    pub fn write_options(&self) -> WriteOptions {
+        let mut opts = WriteOptions::default();
+        opts.disable_wal(self.disable_wal);
+        opts
+    }
+}
+
+/// Config the stores are opened with, read from the environment on the first call
+pub fn active_config() -> RocksDbConfig {
+    ACTIVE_CONFIG.get_or_init(RocksDbConfig::from_env).clone()
+}
+
+/// Benchmark label of a RocksDB store, recording the active profile
+pub fn rocksdb_label(store: &str) -> String {
+    format!("{}[{}]", store, active_config().name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RocksDbConfig, PROFILES};
+
+    #[test]
+    fn every_profile_is_named() {
+        for name in PROFILES {
+            let config = RocksDbConfig::profile(name).unwrap();
+            assert_eq!(config.name, name);
+            config.options();
+            config.write_options();
+        }
+        assert!(RocksDbConfig::profile("fast").is_none());
+    }
+}
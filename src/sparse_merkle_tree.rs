@@ -1,60 +1,632 @@
-use criterion::{Bencher, Criterion};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use criterion::measurement::WallTime;
+use criterion::{BatchSize, Bencher, BenchmarkGroup, Criterion};
+use monotree::{Database, Hash};
+use sparse_merkle_tree::merge::MergeValue;
 use sparse_merkle_tree::traits::{Hasher, StoreReadOps, StoreWriteOps};
-use sparse_merkle_tree::{SparseMerkleTree, H256};
+use sparse_merkle_tree::{MerkleProof, SparseMerkleTree, H256};
 
+use crate::backend::SmtBackend;
+use crate::bench_dir::SNAPSHOT_ROOT;
 use crate::blake3::Blake3SmtHasher;
+use crate::checkpoint::{CheckpointStore, SharedStore};
+use crate::counting_store::CountingStore;
+use crate::digest_hasher::{Blake2bSmtHasher, Keccak256SmtHasher, Sha256SmtHasher};
+use crate::hasher::SmtHasher;
 use crate::memory_store::MemoryStore;
+use crate::poseidon::PoseidonSmtHasher;
+use crate::profile::WorkloadProfile;
 use crate::rocksdb::SmtRockSdb;
+use crate::rocksdb_cf::SmtRockSdbCf;
+use crate::rocksdb_config::rocksdb_label;
+use crate::suite::{
+    bench_backend, bench_backend_proofs, bench_blocks, bench_checkpoints, bench_loaded_backend,
+    bench_profiles, bench_tree_sizes, fill_backend, filled_backend, report_footprint,
+    report_proof_size, report_store_calls,
+};
+use crate::workload::Workload;
 
-fn random_hash() -> H256 {
-    monotree::utils::random_hash().into()
-}
+/// Seed of the snapshot benchmarks, fixed so that the later runs find the saved tree
+const SNAPSHOT_SEED: u64 = 0;
 
 pub fn add_sparse_merkle_tree_benches(c: &mut Criterion, sample_size: usize, tree_size: usize) {
+    report_footprint(
+        "sparse-merkle-tree/memstore+blake3",
+        init_sparse_merkle_tree_memorystore::<Blake3SmtHasher>,
+        tree_size,
+    );
+    report_footprint(
+        "sparse-merkle-tree/rocksdb+blake3",
+        init_sparse_merkle_tree_rocksdb::<Blake3SmtHasher>,
+        tree_size,
+    );
+    report_store_calls(
+        "sparse-merkle-tree/memstore+blake3",
+        init_sparse_merkle_tree_counted_memorystore::<Blake3SmtHasher>,
+        tree_size,
+    );
+    report_store_calls(
+        "sparse-merkle-tree/rocksdb+blake3",
+        init_sparse_merkle_tree_counted_rocksdb::<Blake3SmtHasher>,
+        tree_size,
+    );
+
     let mut group = c.benchmark_group("sparse-merkle-tree");
     group.sample_size(sample_size);
 
-    group.bench_function("memstore+blake3", |b| {
-        test_tree(init_sparse_merkle_tree_blake3_memorystore(), b, tree_size)
+    bench_sparse_merkle_tree_hasher::<Blake3SmtHasher>(&mut group, tree_size);
+    bench_sparse_merkle_tree_hasher::<Sha256SmtHasher>(&mut group, tree_size);
+    bench_sparse_merkle_tree_hasher::<Keccak256SmtHasher>(&mut group, tree_size);
+    bench_sparse_merkle_tree_hasher::<Blake2bSmtHasher>(&mut group, tree_size);
+    bench_sparse_merkle_tree_hasher::<PoseidonSmtHasher>(&mut group, tree_size);
+}
+
+fn bench_sparse_merkle_tree_hasher<H: SmtHasher>(
+    group: &mut BenchmarkGroup<WallTime>,
+    tree_size: usize,
+) {
+    let label = format!("memstore+{}", H::NAME);
+    bench_backend(
+        group,
+        &label,
+        init_sparse_merkle_tree_memorystore::<H>,
+        tree_size,
+    );
+    let label = format!("{}+{}", rocksdb_label("rocksdb"), H::NAME);
+    bench_backend(
+        group,
+        &label,
+        init_sparse_merkle_tree_rocksdb::<H>,
+        tree_size,
+    );
+}
+
+pub fn add_sparse_merkle_tree_proof_benches(
+    c: &mut Criterion,
+    sample_size: usize,
+    tree_size: usize,
+) {
+    let rocksdb = format!("{}+blake3", rocksdb_label("rocksdb"));
+    report_proof_size(
+        "sparse-merkle-tree/proof",
+        "memstore+blake3",
+        init_sparse_merkle_tree_memorystore::<Blake3SmtHasher>,
+        tree_size,
+    );
+    report_proof_size(
+        "sparse-merkle-tree/proof",
+        &rocksdb,
+        init_sparse_merkle_tree_rocksdb::<Blake3SmtHasher>,
+        tree_size,
+    );
+    report_compiled_proof_size(
+        "memstore+blake3",
+        init_sparse_merkle_tree_memorystore::<Blake3SmtHasher>,
+        tree_size,
+    );
+    report_compiled_proof_size(
+        &rocksdb,
+        init_sparse_merkle_tree_rocksdb::<Blake3SmtHasher>,
+        tree_size,
+    );
+
+    let mut group = c.benchmark_group("sparse-merkle-tree/proof");
+    group.sample_size(sample_size);
+
+    bench_sparse_merkle_tree_proof_hasher::<Blake3SmtHasher>(&mut group, tree_size);
+    bench_sparse_merkle_tree_proof_hasher::<Sha256SmtHasher>(&mut group, tree_size);
+    bench_sparse_merkle_tree_proof_hasher::<Keccak256SmtHasher>(&mut group, tree_size);
+    bench_sparse_merkle_tree_proof_hasher::<Blake2bSmtHasher>(&mut group, tree_size);
+    bench_sparse_merkle_tree_proof_hasher::<PoseidonSmtHasher>(&mut group, tree_size);
+}
+
+fn bench_sparse_merkle_tree_proof_hasher<H: SmtHasher>(
+    group: &mut BenchmarkGroup<WallTime>,
+    tree_size: usize,
+) {
+    let label = format!("memstore+{}", H::NAME);
+    bench_backend_proofs(
+        group,
+        &label,
+        init_sparse_merkle_tree_memorystore::<H>,
+        tree_size,
+    );
+    group.bench_function(format!("{}/compile", label), |b| {
+        test_proof_compilation(
+            filled_backend(&label, &init_sparse_merkle_tree_memorystore::<H>, tree_size),
+            b,
+        )
+    });
+
+    let label = format!("{}+{}", rocksdb_label("rocksdb"), H::NAME);
+    bench_backend_proofs(
+        group,
+        &label,
+        init_sparse_merkle_tree_rocksdb::<H>,
+        tree_size,
+    );
+    group.bench_function(format!("{}/compile", label), |b| {
+        test_proof_compilation(
+            filled_backend(&label, &init_sparse_merkle_tree_rocksdb::<H>, tree_size),
+            b,
+        )
     });
+}
+
+pub fn add_sparse_merkle_tree_layout_benches(
+    c: &mut Criterion,
+    sample_size: usize,
+    tree_size: usize,
+) {
+    let mut group = c.benchmark_group("sparse-merkle-tree/rocksdb-layout");
+    group.sample_size(sample_size);
+
+    bench_backend(
+        &mut group,
+        &format!("{}+blake3", rocksdb_label("two-db")),
+        init_sparse_merkle_tree_rocksdb::<Blake3SmtHasher>,
+        tree_size,
+    );
+    bench_backend(
+        &mut group,
+        &format!("{}+blake3", rocksdb_label("column-families")),
+        init_sparse_merkle_tree_rocksdb_cf::<Blake3SmtHasher>,
+        tree_size,
+    );
+}
+
+pub fn add_sparse_merkle_tree_block_benches(
+    c: &mut Criterion,
+    sample_size: usize,
+    tree_size: usize,
+    block_sizes: &[usize],
+) {
+    let mut group = c.benchmark_group("sparse-merkle-tree/blocks");
+    group.sample_size(sample_size);
+
+    let init = init_sparse_merkle_tree_memorystore::<Blake3SmtHasher>;
+    bench_blocks(&mut group, "memstore+blake3", init, tree_size, block_sizes);
+    let label = format!("{}+blake3", rocksdb_label("rocksdb"));
+    bench_blocks(
+        &mut group,
+        &label,
+        init_sparse_merkle_tree_rocksdb::<Blake3SmtHasher>,
+        tree_size,
+        block_sizes,
+    );
+}
+
+// Speculative blocks reverted, the stores undo the writes made since the checkpoint
+pub fn add_sparse_merkle_tree_checkpoint_benches(
+    c: &mut Criterion,
+    sample_size: usize,
+    tree_size: usize,
+    update_counts: &[usize],
+) {
+    let mut group = c.benchmark_group("sparse-merkle-tree/checkpoints");
+    group.sample_size(sample_size);
+
+    let init = init_sparse_merkle_tree_memorystore::<Blake3SmtHasher>;
+    bench_checkpoints(
+        &mut group,
+        "memstore+blake3",
+        init,
+        tree_size,
+        update_counts,
+    );
+    let label = format!("{}+blake3", rocksdb_label("rocksdb"));
+    bench_checkpoints(
+        &mut group,
+        &label,
+        init_sparse_merkle_tree_rocksdb::<Blake3SmtHasher>,
+        tree_size,
+        update_counts,
+    );
+}
+
+pub fn add_sparse_merkle_tree_profile_benches(
+    c: &mut Criterion,
+    sample_size: usize,
+    tree_size: usize,
+) {
+    let mut group = c.benchmark_group("sparse-merkle-tree/profiles");
+    group.sample_size(sample_size);
+
+    let profiles = WorkloadProfile::all();
+    let init = init_sparse_merkle_tree_memorystore::<Blake3SmtHasher>;
+    bench_profiles(&mut group, "memstore+blake3", init, tree_size, &profiles);
+    let label = format!("{}+blake3", rocksdb_label("rocksdb"));
+    bench_profiles(
+        &mut group,
+        &label,
+        init_sparse_merkle_tree_rocksdb::<Blake3SmtHasher>,
+        tree_size,
+        &profiles,
+    );
+}
+
+pub fn add_sparse_merkle_tree_tree_size_benches(
+    c: &mut Criterion,
+    sample_size: usize,
+    tree_sizes: &[usize],
+) {
+    let mut group = c.benchmark_group("sparse-merkle-tree/tree-size");
+    group.sample_size(sample_size);
+
+    bench_tree_sizes(
+        &mut group,
+        "memstore+blake3",
+        init_sparse_merkle_tree_memorystore::<Blake3SmtHasher>,
+        tree_sizes,
+    );
+    bench_tree_sizes(
+        &mut group,
+        &format!("{}+blake3", rocksdb_label("rocksdb")),
+        init_sparse_merkle_tree_rocksdb::<Blake3SmtHasher>,
+        tree_sizes,
+    );
+}
 
-    group.bench_function("rocksdb+blake3", |b| {
-        test_tree(init_sparse_merkle_tree_blake3_rocksdb(), b, tree_size)
+// The same tree in memory and in RocksDB, loaded from a snapshot that the later runs reuse
+pub fn add_sparse_merkle_tree_snapshot_benches(
+    c: &mut Criterion,
+    sample_size: usize,
+    tree_size: usize,
+) {
+    let path = snapshot_path(tree_size);
+    if !path.exists() {
+        save_snapshot(&path, tree_size);
+    }
+
+    let mut group = c.benchmark_group("sparse-merkle-tree/snapshot");
+    group.sample_size(sample_size);
+
+    group.bench_function("memstore+blake3/load", |b| {
+        b.iter(|| MemoryStore::<Blake3SmtHasher>::load(&path).unwrap())
     });
+
+    let store: MemoryStore = MemoryStore::load(&path).unwrap();
+    let memstore =
+        SparseMerkleTreeBackend::<Blake3SmtHasher, _>::from_store(store.clone()).unwrap();
+    bench_loaded_backend(
+        &mut group,
+        "memstore+blake3",
+        &memstore,
+        SNAPSHOT_SEED,
+        tree_size,
+    );
+
+    let mut rocksdb: SmtRockSdb = SmtRockSdb::default();
+    store.copy_into(&mut rocksdb).unwrap();
+    let rocksdb = SparseMerkleTreeBackend::<Blake3SmtHasher, _>::from_store(rocksdb).unwrap();
+    assert_eq!(rocksdb.root(), memstore.root());
+    bench_loaded_backend(
+        &mut group,
+        &format!("{}+blake3", rocksdb_label("rocksdb")),
+        &rocksdb,
+        SNAPSHOT_SEED,
+        tree_size,
+    );
+}
+
+/// Snapshot of the blake3 tree holding the first `tree_size` entries of the snapshot seed
+fn snapshot_path(tree_size: usize) -> PathBuf {
+    Path::new(SNAPSHOT_ROOT).join(format!(
+        "sparse_merkle_tree_blake3_{}_{}.smt",
+        tree_size, SNAPSHOT_SEED
+    ))
+}
+
+// Written aside then renamed, an interrupted run does not leave a truncated snapshot behind
+fn save_snapshot(path: &Path, tree_size: usize) {
+    let mut backend = init_sparse_merkle_tree_memorystore::<Blake3SmtHasher>();
+    fill_backend(&mut backend, &mut Workload::new(SNAPSHOT_SEED), tree_size);
+    fs::create_dir_all(SNAPSHOT_ROOT)
+        .unwrap_or_else(|err| panic!("cannot create {}: {}", SNAPSHOT_ROOT, err));
+    let partial = path.with_extension("partial");
+    backend
+        .tree
+        .store()
+        .borrow()
+        .save(&partial)
+        .and_then(|()| Ok(fs::rename(&partial, path)?))
+        .unwrap_or_else(|err| panic!("cannot save the snapshot {}: {}", path.display(), err));
+}
+
+/// The tree owns its store, the backend rolls it back through another handle on it
+pub struct SparseMerkleTreeBackend<H, S> {
+    tree: SparseMerkleTree<H, H256, SharedStore<S>>,
+}
+
+impl<H, S: Default> Default for SparseMerkleTreeBackend<H, S> {
+    fn default() -> Self {
+        SparseMerkleTreeBackend {
+            tree: SparseMerkleTree::new(H256::zero(), SharedStore::default()),
+        }
+    }
+}
+
+impl<H: Hasher + Default, S: StoreReadOps<H256>> SparseMerkleTreeBackend<H, S> {
+    /// Tree over a store already holding one, its root is computed from the top branch
+    pub fn from_store(store: S) -> Result<Self, sparse_merkle_tree::error::Error> {
+        Ok(SparseMerkleTreeBackend {
+            tree: SparseMerkleTree::new_with_store(SharedStore::new(store))?,
+        })
+    }
+}
+
+impl<H, S: Database> SparseMerkleTreeBackend<H, S> {
+    /// Runs `update` in a store batch, the leaves and branches it writes are committed together
+    fn batched(
+        &mut self,
+        update: impl FnOnce(
+            &mut SparseMerkleTree<H, H256, SharedStore<S>>,
+        ) -> Result<&H256, sparse_merkle_tree::error::Error>,
+    ) -> Result<(), sparse_merkle_tree::error::Error> {
+        let store_error =
+            |err: monotree::Errors| sparse_merkle_tree::error::Error::Store(err.to_string());
+        let store = self.tree.store().handle();
+        store.borrow_mut().init_batch().map_err(store_error)?;
+        update(&mut self.tree)?;
+        store.borrow_mut().finish_batch().map_err(store_error)?;
+        Ok(())
+    }
 }
 
-fn fill_smt<H, S>(tree: &mut SparseMerkleTree<H, H256, S>, nb: usize)
+impl<H, S> SmtBackend for SparseMerkleTreeBackend<H, S>
 where
     H: Hasher + Default,
-    S: StoreReadOps<H256> + StoreWriteOps<H256>,
+    S: StoreReadOps<H256> + StoreWriteOps<H256> + Database + CheckpointStore + Clone,
 {
-    for _ in 0..nb {
-        let key = random_hash();
-        let val = random_hash();
-        tree.update(key, val).unwrap();
+    type Proof = MerkleProof;
+    type Error = sparse_merkle_tree::error::Error;
+    // The tree cannot be given another root, it is rebuilt over the rolled back store
+    type Checkpoint = (H256, S::Checkpoint);
+
+    fn insert(&mut self, key: &Hash, value: &Hash) -> Result<(), Self::Error> {
+        self.batched(|tree| tree.update((*key).into(), (*value).into()))?;
+        Ok(())
+    }
+
+    fn get(&mut self, key: &Hash) -> Result<Option<Hash>, Self::Error> {
+        let value = self.tree.get(&(*key).into())?;
+        Ok((!value.is_zero()).then(|| value.into()))
+    }
+
+    // Updating to zero is how sparse-merkle-tree deletes a leaf
+    fn remove(&mut self, key: &Hash) -> Result<(), Self::Error> {
+        self.batched(|tree| tree.update((*key).into(), H256::zero()))?;
+        Ok(())
+    }
+
+    fn root(&self) -> Hash {
+        (*self.tree.root()).into()
+    }
+
+    fn prove(&mut self, key: &Hash) -> Result<Self::Proof, Self::Error> {
+        self.tree.merkle_proof(vec![(*key).into()])
+    }
+
+    fn verify(&self, key: &Hash, value: &Hash, proof: Self::Proof) -> bool {
+        proof
+            .verify::<H>(self.tree.root(), vec![((*key).into(), (*value).into())])
+            .unwrap_or(false)
+    }
+
+    // An absent key holds the zero value
+    fn verify_absent(&self, key: &Hash, proof: Self::Proof) -> bool {
+        proof
+            .verify::<H>(self.tree.root(), vec![((*key).into(), H256::zero())])
+            .unwrap_or(false)
+    }
+
+    fn batch_update(&mut self, entries: &[(Hash, Hash)]) -> Result<(), Self::Error> {
+        let leaves = entries
+            .iter()
+            .map(|(key, value)| ((*key).into(), (*value).into()))
+            .collect();
+        self.batched(|tree| tree.update_all(leaves))?;
+        Ok(())
+    }
+
+    // Updating to the zero value deletes the leaf
+    fn batch_remove(&mut self, keys: &[Hash]) -> Result<(), Self::Error> {
+        let leaves = keys
+            .iter()
+            .map(|key| ((*key).into(), H256::zero()))
+            .collect();
+        self.batched(|tree| tree.update_all(leaves))?;
+        Ok(())
+    }
+
+    fn proof_size(proof: &Self::Proof) -> usize {
+        let bitmaps = proof.leaves_bitmap().len() * 32;
+        let path: usize = proof
+            .merkle_path()
+            .iter()
+            .map(|arm| match arm {
+                MergeValue::Value(_) => 32,
+                MergeValue::MergeWithZero { .. } => 32 + 32 + 1,
+                #[cfg(feature = "trie")]
+                MergeValue::ShortCut { .. } => 32 + 32 + 1,
+            })
+            .sum();
+        bitmaps + path
+    }
+
+    fn fork(&self) -> Self {
+        SparseMerkleTreeBackend {
+            tree: SparseMerkleTree::new(*self.tree.root(), self.tree.store().clone()),
+        }
+    }
+
+    fn checkpoint(&mut self) -> Result<Self::Checkpoint, Self::Error> {
+        let checkpoint = self.tree.store().borrow_mut().checkpoint()?;
+        Ok((*self.tree.root(), checkpoint))
+    }
+
+    fn rollback(&mut self, (root, checkpoint): Self::Checkpoint) -> Result<(), Self::Error> {
+        self.tree.store().borrow_mut().rollback(checkpoint)?;
+        self.tree = SparseMerkleTree::new(root, self.tree.store().handle());
+        Ok(())
+    }
+
+    fn release(&mut self, (_, checkpoint): Self::Checkpoint) -> Result<(), Self::Error> {
+        self.tree.store().borrow_mut().release(checkpoint)?;
+        Ok(())
     }
 }
 
-fn test_tree<H, S>(mut tree: SparseMerkleTree<H, H256, S>, b: &mut Bencher, tree_size: usize)
-where
+// Compile then verify the compiled form, what a light client receiving a compiled proof pays
+fn test_proof_compilation<H, S>(
+    (mut backend, mut workload): (SparseMerkleTreeBackend<H, S>, Workload),
+    b: &mut Bencher,
+) where
     H: Hasher + Default,
-    S: StoreReadOps<H256> + StoreWriteOps<H256>,
+    S: StoreReadOps<H256> + StoreWriteOps<H256> + Database + CheckpointStore + Clone,
 {
-    fill_smt(&mut tree, tree_size);
-    let key = random_hash();
-    let leaf = random_hash();
-    b.iter(move || {
-        tree.update(key, leaf).unwrap();
-        let _ = tree.get(&key).unwrap();
-        tree.store_mut().remove_leaf(&key).unwrap();
-    })
+    let (key, leaf) = workload.entry();
+    backend.insert(&key, &leaf).unwrap();
+    let root = backend.root().into();
+    let proof = backend.prove(&key).unwrap();
+    b.iter_batched(
+        || proof.clone(),
+        |proof| {
+            let compiled = proof.compile(vec![key.into()]).unwrap();
+            assert!(compiled
+                .verify::<H>(&root, vec![(key.into(), leaf.into())])
+                .unwrap());
+        },
+        BatchSize::SmallInput,
+    )
 }
 
-fn init_sparse_merkle_tree_blake3_memorystore(
-) -> SparseMerkleTree<Blake3SmtHasher, H256, MemoryStore> {
-    SparseMerkleTree::default()
+/// Number of keys the compiled proof size is averaged over
+const PROOF_SIZE_SAMPLES: usize = 100;
+
+fn report_compiled_proof_size<H, S, F>(label: &str, init: F, tree_size: usize)
+where
+    H: Hasher + Default + 'static,
+    S: StoreReadOps<H256> + StoreWriteOps<H256> + Database + CheckpointStore + Clone + 'static,
+    F: Fn() -> SparseMerkleTreeBackend<H, S>,
+{
+    let (mut backend, mut workload) = filled_backend(label, &init, tree_size);
+    let keys: Vec<Hash> = (0..PROOF_SIZE_SAMPLES).map(|_| workload.hash()).collect();
+    for key in keys.iter() {
+        backend.insert(key, &workload.hash()).unwrap();
+    }
+    let total: usize = keys
+        .iter()
+        .map(|key| {
+            let proof = backend.prove(key).unwrap();
+            proof.compile(vec![(*key).into()]).unwrap().0.len()
+        })
+        .sum();
+    println!(
+        "sparse-merkle-tree/proof/{}: average compiled proof size {} bytes",
+        label,
+        total / PROOF_SIZE_SAMPLES
+    );
+}
+
+fn init_sparse_merkle_tree_memorystore<H: SmtHasher>() -> SparseMerkleTreeBackend<H, MemoryStore> {
+    SparseMerkleTreeBackend::default()
+}
+
+fn init_sparse_merkle_tree_rocksdb<H: SmtHasher>() -> SparseMerkleTreeBackend<H, SmtRockSdb> {
+    SparseMerkleTreeBackend::default()
+}
+
+fn init_sparse_merkle_tree_counted_memorystore<H: SmtHasher>(
+) -> SparseMerkleTreeBackend<H, CountingStore<MemoryStore>> {
+    SparseMerkleTreeBackend::default()
+}
+
+fn init_sparse_merkle_tree_counted_rocksdb<H: SmtHasher>(
+) -> SparseMerkleTreeBackend<H, CountingStore<SmtRockSdb>> {
+    SparseMerkleTreeBackend::default()
+}
+
+fn init_sparse_merkle_tree_rocksdb_cf<H: SmtHasher>() -> SparseMerkleTreeBackend<H, SmtRockSdbCf> {
+    SparseMerkleTreeBackend::default()
 }
 
-fn init_sparse_merkle_tree_blake3_rocksdb() -> SparseMerkleTree<Blake3SmtHasher, H256, SmtRockSdb> {
-    SparseMerkleTree::default()
+#[cfg(test)]
+mod tests {
+    use super::{
+        init_sparse_merkle_tree_counted_rocksdb, init_sparse_merkle_tree_memorystore,
+        init_sparse_merkle_tree_rocksdb, init_sparse_merkle_tree_rocksdb_cf,
+        SparseMerkleTreeBackend,
+    };
+    use crate::backend::SmtBackend;
+    use crate::bench_dir::BenchDir;
+    use crate::blake3::Blake3SmtHasher;
+    use crate::memory_store::MemoryStore;
+    use crate::metrics::StoreMetrics;
+    use crate::rocksdb::SmtRockSdb;
+    use crate::suite::{check_backend, fill_backend};
+    use crate::workload::Workload;
+
+    #[test]
+    fn memstore_behaves_as_a_map() {
+        check_backend(init_sparse_merkle_tree_memorystore::<Blake3SmtHasher>);
+    }
+
+    #[test]
+    fn rocksdb_behaves_as_a_map() {
+        check_backend(init_sparse_merkle_tree_rocksdb::<Blake3SmtHasher>);
+    }
+
+    #[test]
+    fn rocksdb_cf_behaves_as_a_map() {
+        check_backend(init_sparse_merkle_tree_rocksdb_cf::<Blake3SmtHasher>);
+    }
+
+    // Each update or block of updates is one batch, whatever the number of nodes it writes
+    #[test]
+    fn updates_are_written_in_one_batch() {
+        let (mut backend, metrics) =
+            StoreMetrics::record(init_sparse_merkle_tree_counted_rocksdb::<Blake3SmtHasher>);
+        let mut workload = Workload::new(7);
+        let (key, leaf) = workload.entry();
+        backend.insert(&key, &leaf).unwrap();
+        backend.batch_update(&workload.entries(10)).unwrap();
+        backend.remove(&key).unwrap();
+
+        let calls = metrics.calls();
+        assert_eq!(calls["init_batch"].calls, 3);
+        assert_eq!(calls["finish_batch"].calls, 3);
+        assert!(calls["insert_branch"].calls > 3);
+    }
+
+    #[test]
+    fn loaded_snapshot_gives_the_same_tree_in_every_store() {
+        let mut backend = init_sparse_merkle_tree_memorystore::<Blake3SmtHasher>();
+        let mut workload = Workload::new(7);
+        fill_backend(&mut backend, &mut workload, 200);
+        let dir = BenchDir::new("sparse_merkle_tree_snapshot");
+        let path = dir.path().join("tree");
+        backend.tree.store().borrow().save(&path).unwrap();
+
+        let store: MemoryStore = MemoryStore::load(&path).unwrap();
+        let mut rocksdb: SmtRockSdb = SmtRockSdb::default();
+        store.copy_into(&mut rocksdb).unwrap();
+        let mut loaded = SparseMerkleTreeBackend::<Blake3SmtHasher, _>::from_store(store).unwrap();
+        let mut copied =
+            SparseMerkleTreeBackend::<Blake3SmtHasher, _>::from_store(rocksdb).unwrap();
+        assert_eq!(loaded.root(), backend.root());
+        assert_eq!(copied.root(), backend.root());
+
+        let (key, leaf) = Workload::new(7).entry();
+        assert_eq!(loaded.get(&key).unwrap(), Some(leaf));
+        assert_eq!(copied.get(&key).unwrap(), Some(leaf));
+        let (key, leaf) = workload.entry();
+        backend.insert(&key, &leaf).unwrap();
+        loaded.insert(&key, &leaf).unwrap();
+        copied.insert(&key, &leaf).unwrap();
+        assert_eq!(loaded.root(), backend.root());
+        assert_eq!(copied.root(), backend.root());
+    }
 }
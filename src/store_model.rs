@@ -0,0 +1,238 @@
+//! Random operation sequences run on a store and on a `BTreeMap`, the results have to match
+
+use std::collections::BTreeMap;
+
+use lsmtree::bytes::Bytes;
+use lsmtree::KVStore;
+use monotree::Database;
+use proptest::collection::vec;
+use proptest::prelude::*;
+use sparse_merkle_tree::merge::MergeValue;
+use sparse_merkle_tree::traits::{StoreReadOps, StoreWriteOps};
+use sparse_merkle_tree::{BranchKey, BranchNode, H256};
+
+use crate::error::StoreError;
+
+/// Longest sequence of operations, shrinking finds the shortest failing one
+const MAX_OPS: usize = 50;
+
+// Few distinct keys, so that the sequences read, overwrite and remove the same keys often
+fn key() -> impl Strategy<Value = [u8; 32]> {
+    (0..4u8).prop_map(|byte| [byte; 32])
+}
+
+fn all_keys() -> impl Iterator<Item = [u8; 32]> {
+    (0..4u8).map(|byte| [byte; 32])
+}
+
+fn value() -> impl Strategy<Value = Vec<u8>> {
+    vec(any::<u8>(), 0..40)
+}
+
+fn hash() -> impl Strategy<Value = H256> {
+    any::<[u8; 32]>().prop_map(H256::from)
+}
+
+#[derive(Debug, Clone)]
+pub enum DatabaseOp {
+    Get([u8; 32]),
+    Put([u8; 32], Vec<u8>),
+    Delete([u8; 32]),
+    InitBatch,
+    FinishBatch,
+}
+
+pub fn database_ops() -> impl Strategy<Value = Vec<DatabaseOp>> {
+    let op = prop_oneof![
+        key().prop_map(DatabaseOp::Get),
+        (key(), value()).prop_map(|(key, value)| DatabaseOp::Put(key, value)),
+        key().prop_map(DatabaseOp::Delete),
+        Just(DatabaseOp::InitBatch),
+        Just(DatabaseOp::FinishBatch),
+    ];
+    vec(op, 0..MAX_OPS)
+}
+
+/// Reads see the writes of the pending batch, the model ignores the batches
+pub fn check_database<D: Database>(
+    mut store: D,
+    ops: Vec<DatabaseOp>,
+) -> Result<(), TestCaseError> {
+    let mut model = BTreeMap::new();
+    for op in ops {
+        match op {
+            DatabaseOp::Get(key) => {
+                prop_assert_eq!(store.get(&key).unwrap(), model.get(&key).cloned())
+            }
+            DatabaseOp::Put(key, value) => {
+                store.put(&key, value.clone()).unwrap();
+                model.insert(key, value);
+            }
+            DatabaseOp::Delete(key) => {
+                store.delete(&key).unwrap();
+                model.remove(&key);
+            }
+            DatabaseOp::InitBatch => store.init_batch().unwrap(),
+            DatabaseOp::FinishBatch => store.finish_batch().unwrap(),
+        }
+    }
+    store.finish_batch().unwrap();
+    for key in all_keys() {
+        prop_assert_eq!(store.get(&key).unwrap(), model.get(&key).cloned());
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub enum SmtStoreOp {
+    GetLeaf(H256),
+    InsertLeaf(H256, H256),
+    RemoveLeaf(H256),
+    GetBranch(BranchKey),
+    InsertBranch(BranchKey, BranchNode),
+    RemoveBranch(BranchKey),
+}
+
+fn branch_key() -> impl Strategy<Value = BranchKey> {
+    (0..2u8, key()).prop_map(|(height, node_key)| BranchKey::new(height, node_key.into()))
+}
+
+fn all_branch_keys() -> impl Iterator<Item = BranchKey> {
+    (0..2u8).flat_map(|height| all_keys().map(move |key| BranchKey::new(height, key.into())))
+}
+
+fn merge_value() -> BoxedStrategy<MergeValue> {
+    let arms = prop_oneof![
+        hash().prop_map(MergeValue::Value),
+        (hash(), hash(), any::<u8>()).prop_map(|(base_node, zero_bits, zero_count)| {
+            MergeValue::MergeWithZero {
+                base_node,
+                zero_bits,
+                zero_count,
+            }
+        }),
+    ];
+    #[cfg(feature = "trie")]
+    let arms = prop_oneof![
+        arms,
+        (hash(), hash(), any::<u8>()).prop_map(|(key, value, height)| MergeValue::ShortCut {
+            key,
+            value,
+            height
+        }),
+    ];
+    arms.boxed()
+}
+
+pub fn branch_node() -> impl Strategy<Value = BranchNode> {
+    (merge_value(), merge_value()).prop_map(|(left, right)| BranchNode { left, right })
+}
+
+pub fn smt_store_ops() -> impl Strategy<Value = Vec<SmtStoreOp>> {
+    let leaf_key = || key().prop_map(H256::from);
+    let op = prop_oneof![
+        leaf_key().prop_map(SmtStoreOp::GetLeaf),
+        (leaf_key(), hash()).prop_map(|(key, leaf)| SmtStoreOp::InsertLeaf(key, leaf)),
+        leaf_key().prop_map(SmtStoreOp::RemoveLeaf),
+        branch_key().prop_map(SmtStoreOp::GetBranch),
+        (branch_key(), branch_node())
+            .prop_map(|(key, branch)| SmtStoreOp::InsertBranch(key, branch)),
+        branch_key().prop_map(SmtStoreOp::RemoveBranch),
+    ];
+    vec(op, 0..MAX_OPS)
+}
+
+pub fn check_smt_store<S>(mut store: S, ops: Vec<SmtStoreOp>) -> Result<(), TestCaseError>
+where
+    S: StoreReadOps<H256> + StoreWriteOps<H256>,
+{
+    let mut leaves = BTreeMap::new();
+    let mut branches = BTreeMap::new();
+    for op in ops {
+        match op {
+            SmtStoreOp::GetLeaf(key) => {
+                prop_assert_eq!(store.get_leaf(&key).unwrap(), leaves.get(&key).cloned())
+            }
+            SmtStoreOp::InsertLeaf(key, leaf) => {
+                store.insert_leaf(key, leaf).unwrap();
+                leaves.insert(key, leaf);
+            }
+            SmtStoreOp::RemoveLeaf(key) => {
+                store.remove_leaf(&key).unwrap();
+                leaves.remove(&key);
+            }
+            SmtStoreOp::GetBranch(key) => {
+                prop_assert_eq!(store.get_branch(&key).unwrap(), branches.get(&key).cloned())
+            }
+            SmtStoreOp::InsertBranch(key, branch) => {
+                store.insert_branch(key.clone(), branch.clone()).unwrap();
+                branches.insert(key, branch);
+            }
+            SmtStoreOp::RemoveBranch(key) => {
+                store.remove_branch(&key).unwrap();
+                branches.remove(&key);
+            }
+        }
+    }
+    for key in all_keys().map(H256::from) {
+        prop_assert_eq!(store.get_leaf(&key).unwrap(), leaves.get(&key).cloned());
+    }
+    for key in all_branch_keys() {
+        prop_assert_eq!(store.get_branch(&key).unwrap(), branches.get(&key).cloned());
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub enum KvOp {
+    Get([u8; 32]),
+    Set([u8; 32], Vec<u8>),
+    Remove([u8; 32]),
+    Contains([u8; 32]),
+}
+
+pub fn kv_ops() -> impl Strategy<Value = Vec<KvOp>> {
+    let op = prop_oneof![
+        key().prop_map(KvOp::Get),
+        (key(), value()).prop_map(|(key, value)| KvOp::Set(key, value)),
+        key().prop_map(KvOp::Remove),
+        key().prop_map(KvOp::Contains),
+    ];
+    vec(op, 0..MAX_OPS)
+}
+
+/// Removing an absent key is a `MissingKey` error, and leaves the store unchanged
+pub fn check_kv_store<S>(mut store: S, ops: Vec<KvOp>) -> Result<(), TestCaseError>
+where
+    S: KVStore<Error = StoreError>,
+{
+    let mut model = BTreeMap::new();
+    for op in ops {
+        match op {
+            KvOp::Get(key) => prop_assert_eq!(
+                store.get(&key).unwrap().map(|value| value.to_vec()),
+                model.get(&key).cloned()
+            ),
+            KvOp::Set(key, value) => {
+                store
+                    .set(Bytes::copy_from_slice(&key), Bytes::from(value.clone()))
+                    .unwrap();
+                model.insert(key, value);
+            }
+            KvOp::Remove(key) => match (store.remove(&key), model.remove(&key)) {
+                (Ok(removed), Some(expected)) => prop_assert_eq!(removed.to_vec(), expected),
+                (Err(StoreError::MissingKey(missing)), None) => prop_assert_eq!(missing, key),
+                (removed, expected) => prop_assert!(
+                    false,
+                    "remove gave {:?}, the model had {:?}",
+                    removed,
+                    expected
+                ),
+            },
+            KvOp::Contains(key) => {
+                prop_assert_eq!(store.contains(&key).unwrap(), model.contains_key(&key))
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,762 @@
+use std::any::{Any, TypeId};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use criterion::measurement::WallTime;
+use criterion::{BatchSize, Bencher, BenchmarkGroup, BenchmarkId, Throughput};
+use monotree::Hash;
+
+use crate::backend::SmtBackend;
+use crate::metrics::StoreMetrics;
+use crate::profile::{Operation, OperationStream, WorkloadProfile};
+use crate::workload::Workload;
+
+/// Number of keys the proof size is averaged over
+const PROOF_SIZE_SAMPLES: usize = 100;
+
+/// Number of updates the node writes per update are averaged over
+const FOOTPRINT_UPDATES: usize = 100;
+
+// Backend type, label and tree size; the label tells apart the RocksDB profiles of one store type
+type FixtureKey = (TypeId, String, usize);
+
+thread_local! {
+    // Filled trees, each one a `(B, Workload)`
+    static FIXTURES: RefCell<HashMap<FixtureKey, Box<dyn Any>>> = RefCell::new(HashMap::new());
+}
+
+pub fn bench_backend<B, F>(
+    group: &mut BenchmarkGroup<WallTime>,
+    label: &str,
+    init: F,
+    tree_size: usize,
+) where
+    B: SmtBackend + 'static,
+    F: Fn() -> B,
+{
+    bench_fixture(group, label, || filled_backend(label, &init, tree_size));
+}
+
+/// Same benchmarks as `bench_backend`, on forks of a tree holding the first `tree_size` entries
+/// of the `seed` workload
+///
+/// For trees loaded from a snapshot instead of filled, the workload goes on after the entries.
+pub fn bench_loaded_backend<B: SmtBackend>(
+    group: &mut BenchmarkGroup<WallTime>,
+    label: &str,
+    backend: &B,
+    seed: u64,
+    tree_size: usize,
+) {
+    let mut workload = Workload::new(seed);
+    workload.entries(tree_size);
+    bench_fixture(group, label, || (backend.fork(), workload.clone()));
+}
+
+fn bench_fixture<B, F>(group: &mut BenchmarkGroup<WallTime>, label: &str, fixture: F)
+where
+    B: SmtBackend,
+    F: Fn() -> (B, Workload),
+{
+    group.bench_function(label, |b| test_tree(fixture(), b));
+
+    group.bench_function(format!("{}/read", label), |b| test_read_only(fixture(), b));
+
+    group.bench_function(format!("{}/write", label), |b| {
+        test_write_only(fixture(), b)
+    });
+
+    group.bench_function(format!("{}/read-missing", label), |b| {
+        test_read_missing(fixture(), b)
+    });
+}
+
+pub fn bench_backend_proofs<B, F>(
+    group: &mut BenchmarkGroup<WallTime>,
+    label: &str,
+    init: F,
+    tree_size: usize,
+) where
+    B: SmtBackend + 'static,
+    F: Fn() -> B,
+{
+    group.bench_function(format!("{}/generate", label), |b| {
+        test_proof_generation(filled_backend(label, &init, tree_size), b)
+    });
+
+    // A verification that always fails would not time the real one, so it is left out
+    if B::PROOFS_VERIFY {
+        group.bench_function(format!("{}/verify", label), |b| {
+            test_proof_verification(filled_backend(label, &init, tree_size), b)
+        });
+    }
+
+    if B::PROVES_ABSENCE {
+        group.bench_function(format!("{}/generate-absent", label), |b| {
+            test_absence_proof_generation(filled_backend(label, &init, tree_size), b)
+        });
+    }
+
+    if B::PROVES_ABSENCE && B::PROOFS_VERIFY {
+        group.bench_function(format!("{}/verify-absent", label), |b| {
+            test_absence_proof_verification(filled_backend(label, &init, tree_size), b)
+        });
+    }
+}
+
+pub fn bench_batch_update<B, F>(
+    group: &mut BenchmarkGroup<WallTime>,
+    label: &str,
+    init: F,
+    tree_size: usize,
+    batch_size: usize,
+) where
+    B: SmtBackend + 'static,
+    F: Fn() -> B,
+{
+    group.bench_function(label, |b| {
+        test_batch_update(filled_backend(label, &init, tree_size), b, batch_size)
+    });
+}
+
+/// Blocks of updates then of removals, one benchmark per number of keys in a block
+///
+/// The time is the block latency, the throughput in elements the amortized cost of a key.
+pub fn bench_blocks<B, F>(
+    group: &mut BenchmarkGroup<WallTime>,
+    label: &str,
+    init: F,
+    tree_size: usize,
+    block_sizes: &[usize],
+) where
+    B: SmtBackend + 'static,
+    F: Fn() -> B,
+{
+    for &block_size in block_sizes {
+        group.throughput(Throughput::Elements(block_size as u64));
+        group.bench_function(
+            BenchmarkId::new(format!("{}/update", label), block_size),
+            |b| test_batch_update(filled_backend(label, &init, tree_size), b, block_size),
+        );
+        group.bench_function(
+            BenchmarkId::new(format!("{}/remove", label), block_size),
+            |b| test_batch_remove(filled_backend(label, &init, tree_size), b, block_size),
+        );
+    }
+}
+
+/// Cost of taking a checkpoint, then of rolling back each number of updates applied after one
+pub fn bench_checkpoints<B, F>(
+    group: &mut BenchmarkGroup<WallTime>,
+    label: &str,
+    init: F,
+    tree_size: usize,
+    update_counts: &[usize],
+) where
+    B: SmtBackend + 'static,
+    F: Fn() -> B,
+{
+    group.bench_function(format!("{}/checkpoint", label), |b| {
+        test_checkpoint(filled_backend(label, &init, tree_size), b)
+    });
+    for &updates in update_counts {
+        group.bench_function(
+            BenchmarkId::new(format!("{}/rollback", label), updates),
+            |b| test_rollback(filled_backend(label, &init, tree_size), b, updates),
+        );
+    }
+}
+
+/// Each profile run by the same driver, one operation per iteration
+pub fn bench_profiles<B, F>(
+    group: &mut BenchmarkGroup<WallTime>,
+    label: &str,
+    init: F,
+    tree_size: usize,
+    profiles: &[WorkloadProfile],
+) where
+    B: SmtBackend + 'static,
+    F: Fn() -> B,
+{
+    for profile in profiles {
+        group.bench_function(format!("{}/{}", label, profile.name), |b| {
+            test_profile(
+                filled_backend(label, &init, tree_size),
+                b,
+                profile,
+                tree_size,
+            )
+        });
+    }
+}
+
+/// Insert, get, remove and prove on the same tree filled up to each of `tree_sizes`
+///
+/// The tree is filled once and grown from one size to the next, the sizes have to be increasing.
+pub fn bench_tree_sizes<B, F>(
+    group: &mut BenchmarkGroup<WallTime>,
+    label: &str,
+    init: F,
+    tree_sizes: &[usize],
+) where
+    B: SmtBackend,
+    F: Fn() -> B,
+{
+    assert!(
+        tree_sizes.windows(2).all(|w| w[0] < w[1]),
+        "tree sizes {:?} are not increasing",
+        tree_sizes
+    );
+    let mut backend = init();
+    let mut workload = Workload::seeded();
+    let mut filled = 0;
+    for &tree_size in tree_sizes {
+        fill_backend(&mut backend, &mut workload, tree_size - filled);
+        filled = tree_size;
+        let (key, leaf) = workload.entry();
+        backend.insert(&key, &leaf).unwrap();
+
+        group.bench_function(
+            BenchmarkId::new(format!("{}/insert", label), tree_size),
+            |b| test_sized_insert(&mut backend, &mut workload, b),
+        );
+        group.bench_function(BenchmarkId::new(format!("{}/get", label), tree_size), |b| {
+            b.iter(|| backend.get(&key).unwrap())
+        });
+        group.bench_function(
+            BenchmarkId::new(format!("{}/remove", label), tree_size),
+            |b| test_sized_remove(&mut backend, &mut workload, b),
+        );
+        group.bench_function(
+            BenchmarkId::new(format!("{}/prove", label), tree_size),
+            |b| b.iter(|| backend.prove(&key).unwrap()),
+        );
+
+        backend.remove(&key).unwrap();
+    }
+}
+
+pub fn fill_backend<B: SmtBackend>(backend: &mut B, workload: &mut Workload, nb: usize) {
+    for _ in 0..nb {
+        let (key, leaf) = workload.entry();
+        backend.insert(&key, &leaf).unwrap();
+    }
+}
+
+/// Fork of a tree holding `tree_size` entries, and the workload to go on with
+///
+/// The tree is filled once per backend type, label and size, and kept until `drop_fixtures`.
+/// The labels of the RocksDB stores name their profile, so each profile gets its own tree.
+pub fn filled_backend<B, F>(label: &str, init: &F, tree_size: usize) -> (B, Workload)
+where
+    B: SmtBackend + 'static,
+    F: Fn() -> B,
+{
+    FIXTURES.with(|fixtures| {
+        let mut fixtures = fixtures.borrow_mut();
+        let fixture = fixtures
+            .entry((TypeId::of::<B>(), label.to_string(), tree_size))
+            .or_insert_with(|| {
+                let mut backend = init();
+                let mut workload = Workload::seeded();
+                fill_backend(&mut backend, &mut workload, tree_size);
+                Box::new((backend, workload))
+            });
+        let (backend, workload) = fixture.downcast_ref::<(B, Workload)>().unwrap();
+        (backend.fork(), workload.clone())
+    })
+}
+
+/// Keys of the trees built by `filled_backend`, in insertion order
+fn filled_keys(tree_size: usize) -> Vec<Hash> {
+    let mut workload = Workload::seeded();
+    (0..tree_size).map(|_| workload.entry().0).collect()
+}
+
+/// Drops the filled trees, deleting the directories of those on disk
+pub fn drop_fixtures() {
+    FIXTURES.with(|fixtures| fixtures.borrow_mut().clear());
+}
+
+/// Checks that the tree built by `init` behaves as a map, whatever the order of the writes
+///
+/// The tests of every framework run it on the same entries.
+#[cfg(test)]
+pub(crate) fn check_backend<B, F>(init: F)
+where
+    B: SmtBackend,
+    F: Fn() -> B,
+{
+    const ENTRIES: usize = 200;
+    let mut workload = Workload::new(7);
+    let entries = workload.entries(ENTRIES);
+    let missing = workload.hash();
+
+    let mut backend = init();
+    assert_eq!(backend.root(), [0; 32]);
+    for (key, value) in entries.iter() {
+        backend.insert(key, value).unwrap();
+    }
+    for (key, value) in entries.iter() {
+        assert_eq!(backend.get(key).unwrap(), Some(*value));
+    }
+    assert_eq!(backend.get(&missing).unwrap(), None);
+    let root = backend.root();
+
+    let mut reordered = init();
+    let mut sorted = entries.clone();
+    if B::ROOT_IGNORES_ORDER {
+        for (key, value) in entries.iter().rev() {
+            reordered.insert(key, value).unwrap();
+        }
+    } else {
+        // Only the same order has to give the same root, the bulk API may sort the entries
+        for (key, value) in entries.iter() {
+            reordered.insert(key, value).unwrap();
+        }
+        sorted.sort();
+    }
+    assert_eq!(
+        reordered.root(),
+        root,
+        "root depends on the insertion order"
+    );
+    let mut in_order = init();
+    for (key, value) in sorted.iter() {
+        in_order.insert(key, value).unwrap();
+    }
+    let mut batched = init();
+    batched.batch_update(&entries).unwrap();
+    assert_eq!(
+        batched.root(),
+        in_order.root(),
+        "bulk insert gives another root"
+    );
+
+    for (key, value) in entries.iter().step_by(10) {
+        let proof = backend.prove(key).unwrap();
+        if B::PROOFS_VERIFY {
+            assert!(backend.verify(key, value, proof.clone()));
+            assert!(!backend.verify(key, &workload.hash(), proof));
+        }
+    }
+    if B::PROVES_ABSENCE && B::PROOFS_VERIFY {
+        let proof = backend.prove(&missing).unwrap();
+        assert!(backend.verify_absent(&missing, proof));
+    }
+
+    let (key, value) = entries[0];
+    let update = workload.hash();
+    backend.insert(&key, &update).unwrap();
+    assert_eq!(backend.get(&key).unwrap(), Some(update));
+    assert_ne!(backend.root(), root);
+    backend.insert(&key, &value).unwrap();
+    assert_eq!(backend.root(), root, "updating back gives another root");
+    if B::REMOVE_RESTORES_ROOT {
+        backend.insert(&missing, &update).unwrap();
+        backend.remove_present(&missing).unwrap();
+        assert_eq!(backend.root(), root, "remove_present gives another root");
+    }
+
+    // A rollback drops the checkpoints taken after, a release keeps the updates
+    let (other, other_value) = entries[1];
+    let checkpoint = backend.checkpoint().unwrap();
+    backend.insert(&key, &update).unwrap();
+    let updated = backend.root();
+    let nested = backend.checkpoint().unwrap();
+    backend.remove(&other).unwrap();
+    backend.insert(&missing, &update).unwrap();
+    let dropped = backend.checkpoint().unwrap();
+    backend.insert(&other, &update).unwrap();
+    backend.rollback(nested).unwrap();
+    assert_eq!(backend.root(), updated, "rollback gives another root");
+    assert!(
+        backend.rollback(dropped).is_err(),
+        "rolled back to a dropped checkpoint"
+    );
+    assert_eq!(
+        backend.root(),
+        updated,
+        "a refused rollback changes the root"
+    );
+    assert_eq!(backend.get(&other).unwrap(), Some(other_value));
+    assert_eq!(backend.get(&missing).unwrap(), None);
+    let released = backend.checkpoint().unwrap();
+    backend.insert(&missing, &update).unwrap();
+    backend.release(released).unwrap();
+    assert_eq!(backend.get(&missing).unwrap(), Some(update));
+    backend.rollback(checkpoint).unwrap();
+    assert_eq!(backend.root(), root, "rollback gives another root");
+    assert_eq!(backend.get(&key).unwrap(), Some(value));
+    assert_eq!(backend.get(&missing).unwrap(), None);
+    let released = backend.checkpoint().unwrap();
+    backend.release(released).unwrap();
+
+    let (removed, kept) = entries.split_at(ENTRIES / 2);
+    let removed_keys: Vec<_> = removed.iter().map(|(key, _)| *key).collect();
+    for key in removed_keys.iter() {
+        backend.remove(key).unwrap();
+    }
+    batched.batch_remove(&removed_keys).unwrap();
+    for key in removed_keys.iter() {
+        assert_eq!(backend.get(key).unwrap(), None);
+        assert_eq!(batched.get(key).unwrap(), None);
+    }
+    for (key, value) in kept.iter() {
+        assert_eq!(backend.get(key).unwrap(), Some(*value));
+        assert_eq!(batched.get(key).unwrap(), Some(*value));
+    }
+    if B::REMOVE_RESTORES_ROOT {
+        let mut rebuilt = init();
+        for (key, value) in kept.iter() {
+            rebuilt.insert(key, value).unwrap();
+        }
+        assert_eq!(
+            backend.root(),
+            rebuilt.root(),
+            "removed keys still change the root"
+        );
+        assert_eq!(
+            batched.root(),
+            rebuilt.root(),
+            "bulk remove gives another root"
+        );
+        for (key, _) in kept.iter() {
+            backend.remove(key).unwrap();
+        }
+        assert_eq!(backend.root(), [0; 32]);
+    }
+}
+
+// Removing the key inserted on top of a tree has to give back the previous root,
+// otherwise the remove benchmarked is not a real delete
+fn assert_remove_restores_root<B: SmtBackend>(backend: &mut B, key: &Hash, leaf: &Hash) {
+    let root = backend.root();
+    backend.insert(key, leaf).unwrap();
+    assert_ne!(backend.root(), root);
+    backend.remove(key).unwrap();
+    assert_eq!(backend.get(key).unwrap(), None);
+    if B::REMOVE_RESTORES_ROOT {
+        assert_eq!(backend.root(), root);
+    }
+}
+
+fn test_tree<B: SmtBackend>((mut backend, mut workload): (B, Workload), b: &mut Bencher) {
+    let (key, leaf) = workload.entry();
+    assert_remove_restores_root(&mut backend, &key, &leaf);
+    b.iter(move || {
+        backend.insert(&key, &leaf).unwrap();
+        let found = backend.get(&key).unwrap();
+        backend.remove_present(&key).unwrap();
+        found
+    })
+}
+
+fn test_read_only<B: SmtBackend>((mut backend, mut workload): (B, Workload), b: &mut Bencher) {
+    let (key, leaf) = workload.entry();
+    backend.insert(&key, &leaf).unwrap();
+    assert_eq!(backend.get(&key).unwrap(), Some(leaf));
+    b.iter(move || backend.get(&key).unwrap())
+}
+
+fn test_read_missing<B: SmtBackend>((mut backend, mut workload): (B, Workload), b: &mut Bencher) {
+    let key = workload.hash();
+    assert_eq!(backend.get(&key).unwrap(), None);
+    b.iter(move || backend.get(&key).unwrap())
+}
+
+fn test_write_only<B: SmtBackend>((mut backend, mut workload): (B, Workload), b: &mut Bencher) {
+    let (key, leaf) = workload.entry();
+    assert_remove_restores_root(&mut backend, &key, &leaf);
+    b.iter(move || {
+        backend.insert(&key, &leaf).unwrap();
+        backend.remove_present(&key).unwrap();
+    })
+}
+
+// The same keys get new values at every iteration, so the tree size stays the same
+fn test_batch_update<B: SmtBackend>(
+    (mut backend, mut workload): (B, Workload),
+    b: &mut Bencher,
+    batch_size: usize,
+) {
+    let keys: Vec<Hash> = (0..batch_size).map(|_| workload.hash()).collect();
+    b.iter_batched(
+        || {
+            keys.iter()
+                .map(|key| (*key, workload.hash()))
+                .collect::<Vec<_>>()
+        },
+        |entries| backend.batch_update(&entries).unwrap(),
+        BatchSize::SmallInput,
+    )
+}
+
+// Each block removes keys inserted out of the measurement, so the tree size stays the same
+fn test_batch_remove<B: SmtBackend>(
+    (mut backend, mut workload): (B, Workload),
+    b: &mut Bencher,
+    batch_size: usize,
+) {
+    b.iter_custom(|iters| {
+        let mut elapsed = Duration::ZERO;
+        for _ in 0..iters {
+            let entries = workload.entries(batch_size);
+            backend.batch_update(&entries).unwrap();
+            let keys: Vec<Hash> = entries.iter().map(|(key, _)| *key).collect();
+            let start = Instant::now();
+            backend.batch_remove_present(&keys).unwrap();
+            elapsed += start.elapsed();
+        }
+        elapsed
+    })
+}
+
+// Each checkpoint is released out of the measurement, so that none is left to slow the writes
+fn test_checkpoint<B: SmtBackend>((mut backend, _): (B, Workload), b: &mut Bencher) {
+    b.iter_custom(|iters| {
+        let mut elapsed = Duration::ZERO;
+        for _ in 0..iters {
+            let start = Instant::now();
+            let checkpoint = backend.checkpoint().unwrap();
+            elapsed += start.elapsed();
+            backend.release(checkpoint).unwrap();
+        }
+        elapsed
+    })
+}
+
+// The checkpoint and the updates are out of the measurement, each rollback gives back the filled tree
+fn test_rollback<B: SmtBackend>(
+    (mut backend, mut workload): (B, Workload),
+    b: &mut Bencher,
+    updates: usize,
+) {
+    let root = backend.root();
+    b.iter_custom(|iters| {
+        let mut elapsed = Duration::ZERO;
+        for _ in 0..iters {
+            let checkpoint = backend.checkpoint().unwrap();
+            backend.batch_update(&workload.entries(updates)).unwrap();
+            let start = Instant::now();
+            backend.rollback(checkpoint).unwrap();
+            elapsed += start.elapsed();
+        }
+        elapsed
+    });
+    assert_eq!(backend.root(), root, "rollback gives another root");
+}
+
+// The operations are drawn out of the measurement
+fn test_profile<B: SmtBackend>(
+    (mut backend, workload): (B, Workload),
+    b: &mut Bencher,
+    profile: &WorkloadProfile,
+    tree_size: usize,
+) {
+    let mut operations = OperationStream::new(profile.clone(), filled_keys(tree_size), workload);
+    b.iter_batched(
+        || operations.next_operation(),
+        |operation| match operation {
+            Operation::Read(key) => {
+                backend.get(&key).unwrap();
+            }
+            Operation::Update(key, value) | Operation::Insert(key, value) => {
+                backend.insert(&key, &value).unwrap()
+            }
+            Operation::Delete(key) => backend.remove(&key).unwrap(),
+        },
+        BatchSize::SmallInput,
+    )
+}
+
+// Every key inserted is removed out of the measurement, so the tree keeps its size
+fn test_sized_insert<B: SmtBackend>(backend: &mut B, workload: &mut Workload, b: &mut Bencher) {
+    b.iter_custom(|iters| {
+        let mut elapsed = Duration::ZERO;
+        for _ in 0..iters {
+            let (key, leaf) = workload.entry();
+            let start = Instant::now();
+            backend.insert(&key, &leaf).unwrap();
+            elapsed += start.elapsed();
+            backend.remove(&key).unwrap();
+        }
+        elapsed
+    })
+}
+
+// Every key removed is inserted out of the measurement, so the tree keeps its size
+fn test_sized_remove<B: SmtBackend>(backend: &mut B, workload: &mut Workload, b: &mut Bencher) {
+    b.iter_custom(|iters| {
+        let mut elapsed = Duration::ZERO;
+        for _ in 0..iters {
+            let (key, leaf) = workload.entry();
+            backend.insert(&key, &leaf).unwrap();
+            let start = Instant::now();
+            backend.remove_present(&key).unwrap();
+            elapsed += start.elapsed();
+        }
+        elapsed
+    })
+}
+
+fn test_proof_generation<B: SmtBackend>(
+    (mut backend, mut workload): (B, Workload),
+    b: &mut Bencher,
+) {
+    let (key, leaf) = workload.entry();
+    backend.insert(&key, &leaf).unwrap();
+    b.iter(move || backend.prove(&key).unwrap())
+}
+
+// Verifying may consume the proof, the clone is kept out of the measurement
+fn test_proof_verification<B: SmtBackend>(
+    (mut backend, mut workload): (B, Workload),
+    b: &mut Bencher,
+) {
+    let (key, leaf) = workload.entry();
+    backend.insert(&key, &leaf).unwrap();
+    let proof = backend.prove(&key).unwrap();
+    assert!(backend.verify(&key, &leaf, proof.clone()));
+    b.iter_batched(
+        || proof.clone(),
+        |proof| backend.verify(&key, &leaf, proof),
+        BatchSize::SmallInput,
+    )
+}
+
+fn test_absence_proof_generation<B: SmtBackend>(
+    (mut backend, mut workload): (B, Workload),
+    b: &mut Bencher,
+) {
+    let key = workload.hash();
+    b.iter(move || backend.prove(&key).unwrap())
+}
+
+fn test_absence_proof_verification<B: SmtBackend>(
+    (mut backend, mut workload): (B, Workload),
+    b: &mut Bencher,
+) {
+    let key = workload.hash();
+    let proof = backend.prove(&key).unwrap();
+    assert!(backend.verify_absent(&key, proof.clone()));
+    b.iter_batched(
+        || proof.clone(),
+        |proof| backend.verify_absent(&key, proof),
+        BatchSize::SmallInput,
+    )
+}
+
+/// Prints the average proof size over `PROOF_SIZE_SAMPLES` keys, under `group/label`
+pub fn report_proof_size<B, F>(group: &str, label: &str, init: F, tree_size: usize)
+where
+    B: SmtBackend + 'static,
+    F: Fn() -> B,
+{
+    let (mut backend, mut workload) = filled_backend(label, &init, tree_size);
+    let keys: Vec<_> = (0..PROOF_SIZE_SAMPLES).map(|_| workload.hash()).collect();
+    for key in keys.iter() {
+        backend.insert(key, &workload.hash()).unwrap();
+    }
+    let total: usize = keys
+        .iter()
+        .map(|key| B::proof_size(&backend.prove(key).unwrap()))
+        .sum();
+    println!(
+        "{}/{}: average proof size {} bytes",
+        group,
+        label,
+        total / PROOF_SIZE_SAMPLES
+    );
+}
+
+/// Prints what the stores hold and how much is written to them after `tree_size` inserts
+///
+/// The tree is filled again instead of forked from the fixtures, so that every store
+/// it opens is counted from the start.
+pub fn report_footprint<B, F>(label: &str, init: F, tree_size: usize)
+where
+    B: SmtBackend,
+    F: Fn() -> B,
+{
+    let (mut backend, metrics) = StoreMetrics::record(init);
+    let mut workload = Workload::seeded();
+    fill_backend(&mut backend, &mut workload, tree_size);
+    let filled = metrics.counts();
+
+    let keys = filled_keys(tree_size);
+    for key in keys.iter().cycle().take(FOOTPRINT_UPDATES) {
+        backend.insert(key, &workload.hash()).unwrap();
+    }
+    let updated = metrics.counts();
+    drop(backend);
+    let footprint = metrics.footprint();
+
+    let leaves = tree_size.max(1) as f64;
+    let updates = FOOTPRINT_UPDATES as f64;
+    println!(
+        "{}: {} entries after {} inserts, {:.1} bytes per leaf, {:.1} node writes per insert",
+        label,
+        footprint.entries,
+        tree_size,
+        footprint.stored_bytes as f64 / leaves,
+        filled.writes as f64 / leaves,
+    );
+    println!(
+        "{}: {:.1} node reads, {:.1} node writes and {:.0} bytes written per update",
+        label,
+        (updated.reads - filled.reads) as f64 / updates,
+        (updated.writes - filled.writes) as f64 / updates,
+        (updated.bytes_written - filled.bytes_written) as f64 / updates,
+    );
+    if !footprint.rocksdb_stats.is_empty() {
+        println!(
+            "{}: {} SST bytes, {:.1} per leaf",
+            label,
+            footprint.sst_bytes,
+            footprint.sst_bytes as f64 / leaves
+        );
+        for line in footprint.rocksdb_counters() {
+            println!("{}: {}", label, line);
+        }
+    }
+}
+
+/// Prints the store calls made by `tree_size` inserts, and the share of the time spent in them
+///
+/// `init` has to wrap its stores in `CountingStore`, the time left is mostly hashing.
+pub fn report_store_calls<B, F>(label: &str, init: F, tree_size: usize)
+where
+    B: SmtBackend,
+    F: Fn() -> B,
+{
+    let (mut backend, metrics) = StoreMetrics::record_latencies(init);
+    let mut workload = Workload::seeded();
+    let start = Instant::now();
+    fill_backend(&mut backend, &mut workload, tree_size);
+    let elapsed = start.elapsed();
+
+    let calls = metrics.calls();
+    let in_store: Duration = calls.values().map(|stats| stats.latency).sum();
+    let inserts = tree_size.max(1) as f64;
+    println!(
+        "{}: {:.1} store calls per insert, {:.0}% of the insert time spent in the store",
+        label,
+        calls.values().map(|stats| stats.calls).sum::<u64>() as f64 / inserts,
+        100.0 * in_store.as_secs_f64() / elapsed.as_secs_f64().max(f64::EPSILON),
+    );
+    for (method, stats) in calls {
+        let per_call = stats.calls.max(1) as f64;
+        println!(
+            "{}: {} {:.1} per insert, {:.1} key bytes, {:.1} value bytes, median {:?}, p99 {:?}",
+            label,
+            method,
+            stats.calls as f64 / inserts,
+            stats.key_bytes as f64 / per_call,
+            stats.value_bytes as f64 / per_call,
+            stats.latency_quantile(0.5).unwrap_or_default(),
+            stats.latency_quantile(0.99).unwrap_or_default(),
+        );
+    }
+}
@@ -0,0 +1,327 @@
+//! Monotree states kept by block height, and pruning of the nodes none of them reaches
+//!
+//! Monotree never deletes a node, every insert leaves the nodes of the previous root behind.
+//! `VersionedMonotree` keeps the roots of the recent blocks, reads at any of them, and prunes
+//! the older ones by marking the nodes the kept roots reach and sweeping the others.
+
+use std::collections::{BTreeMap, HashSet};
+
+use monotree::utils::slice_to_hash;
+use monotree::{Database, Errors, Hash, Hasher, Monotree, Node};
+
+use crate::bench_dir::BenchDir;
+use crate::checkpoint::{open_monotree, SharedStore};
+use crate::error::key_from_slice;
+
+/// Length of a key in bits, a cell ending there holds a leaf instead of a child node
+const KEY_BITS: u16 = 256;
+
+/// A store and the keys of the nodes written to it, the store cannot list them
+pub struct NodeDatabase<D> {
+    db: D,
+    keys: HashSet<Hash>,
+}
+
+impl<D: Database> Database for NodeDatabase<D> {
+    fn new(dbpath: &str) -> Self {
+        NodeDatabase {
+            db: D::new(dbpath),
+            keys: HashSet::new(),
+        }
+    }
+
+    fn get(&mut self, key: &[u8]) -> monotree::Result<Option<Vec<u8>>> {
+        self.db.get(key)
+    }
+
+    fn put(&mut self, key: &[u8], value: Vec<u8>) -> monotree::Result<()> {
+        self.keys.insert(key_from_slice(key)?);
+        self.db.put(key, value)
+    }
+
+    fn delete(&mut self, key: &[u8]) -> monotree::Result<()> {
+        self.keys.remove(key);
+        self.db.delete(key)
+    }
+
+    fn init_batch(&mut self) -> monotree::Result<()> {
+        self.db.init_batch()
+    }
+
+    fn finish_batch(&mut self) -> monotree::Result<()> {
+        self.db.finish_batch()
+    }
+}
+
+/// What a `prune` left in the store
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PruneReport {
+    pub kept_heights: usize,
+    /// Nodes reached from the kept roots
+    pub kept_nodes: usize,
+    pub deleted_nodes: usize,
+}
+
+/// Monotree whose root is recorded for each committed block height
+pub struct VersionedMonotree<D: Database + 'static, H: Hasher> {
+    tree: Monotree<SharedStore<NodeDatabase<D>>, H>,
+    // The database of `tree`, swept by `prune`
+    nodes: SharedStore<NodeDatabase<D>>,
+    // Root after each committed block, `None` for an empty tree
+    roots: BTreeMap<u64, Option<Hash>>,
+    // Dropped after the tree, once its database is closed
+    dir: Option<BenchDir>,
+}
+
+impl<D: Database + 'static, H: Hasher> VersionedMonotree<D, H> {
+    pub fn new(dbpath: &str) -> Self {
+        let nodes = SharedStore::new(NodeDatabase::new(dbpath));
+        VersionedMonotree {
+            tree: open_monotree(&nodes),
+            nodes,
+            roots: BTreeMap::new(),
+            dir: None,
+        }
+    }
+
+    /// Tree whose database lives in `dir`, deleted with the tree
+    pub fn in_dir(dir: BenchDir) -> Self {
+        let mut state = VersionedMonotree::new(&dir.db_path("db"));
+        state.dir = Some(dir);
+        state
+    }
+
+    /// Height of the last committed block
+    pub fn latest_height(&self) -> Option<u64> {
+        self.roots.keys().next_back().copied()
+    }
+
+    /// Applies a block on top of the last one, its root is kept for `height`
+    pub fn commit(
+        &mut self,
+        height: u64,
+        entries: &[(Hash, Hash)],
+        removed: &[Hash],
+    ) -> monotree::Result<Option<Hash>> {
+        let root = match self.roots.iter().next_back() {
+            Some((&latest, _)) if latest >= height => {
+                return Err(Errors::new(&format!(
+                    "block {} committed after block {}",
+                    height, latest
+                )))
+            }
+            Some((_, root)) => *root,
+            None => None,
+        };
+        let (keys, leaves): (Vec<Hash>, Vec<Hash>) = entries.iter().cloned().unzip();
+        let mut root = self.tree.inserts(root.as_ref(), &keys, &leaves)?;
+        // Removing an absent key makes monotree drop the sibling subtree, see `MonotreeBackend`
+        let mut present = Vec::with_capacity(removed.len());
+        for key in removed {
+            if self.tree.get(root.as_ref(), key)?.is_some() {
+                present.push(*key);
+            }
+        }
+        root = self.tree.removes(root.as_ref(), &present)?;
+        self.roots.insert(height, root);
+        Ok(root)
+    }
+
+    /// Root of the state at `height`, the one of the last block committed up to it
+    pub fn root_at(&self, height: u64) -> monotree::Result<Option<Hash>> {
+        match self.roots.range(..=height).next_back() {
+            Some((_, root)) => Ok(*root),
+            None => Err(Errors::new(&format!("no state kept at height {}", height))),
+        }
+    }
+
+    pub fn get_at(&mut self, height: u64, key: &Hash) -> monotree::Result<Option<Hash>> {
+        let root = self.root_at(height)?;
+        self.tree.get(root.as_ref(), key)
+    }
+
+    /// Forgets the states before `height` and deletes the nodes only they reached
+    ///
+    /// The state at `height` stays readable, so the block committed up to it is kept.
+    /// The nodes written while committing and replaced in the same block are deleted too.
+    pub fn prune(&mut self, height: u64) -> monotree::Result<PruneReport> {
+        if let Some((&kept, _)) = self.roots.range(..=height).next_back() {
+            self.roots = self.roots.split_off(&kept);
+        }
+
+        let mut nodes = self.nodes.borrow_mut();
+        let mut visited = HashSet::new();
+        for root in self.roots.values().flatten() {
+            mark(&mut nodes.db, root, 0, &mut visited)?;
+        }
+        let reachable: HashSet<Hash> = visited.into_iter().map(|(hash, _)| hash).collect();
+
+        let garbage: Vec<Hash> = nodes.keys.difference(&reachable).copied().collect();
+        nodes.db.init_batch()?;
+        for key in garbage.iter() {
+            nodes.db.delete(key)?;
+        }
+        nodes.db.finish_batch()?;
+        nodes.keys = reachable;
+        Ok(PruneReport {
+            kept_heights: self.roots.len(),
+            kept_nodes: nodes.keys.len(),
+            deleted_nodes: garbage.len(),
+        })
+    }
+}
+
+/// Adds the node `hash`, `depth` bits below the root, and the nodes under it to `visited`
+///
+/// Whether a cell holds a leaf depends on the depth, so nodes are visited once per depth.
+fn mark<D: Database>(
+    db: &mut D,
+    hash: &Hash,
+    depth: u16,
+    visited: &mut HashSet<(Hash, u16)>,
+) -> monotree::Result<()> {
+    if !visited.insert((*hash, depth)) {
+        return Ok(());
+    }
+    let bytes = db
+        .get(hash)?
+        .ok_or_else(|| Errors::new(&format!("missing node {:?}", hash)))?;
+    let cells = match Node::from_bytes(&bytes)? {
+        Node::Soft(cell) => [cell, None],
+        Node::Hard(left, right) => [left, right],
+    };
+    for unit in cells.into_iter().flatten() {
+        let below = depth + unit.bits.len();
+        if below < KEY_BITS {
+            mark(db, &slice_to_hash(unit.hash), below, visited)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use monotree::Hash;
+
+    use super::VersionedMonotree;
+    use crate::bench_dir::BenchDir;
+    use crate::blake3::Blake3SmtHasher;
+    use crate::memory_store::MemoryStore;
+    use crate::rocksdb::SmtRockSdb;
+    use crate::workload::Workload;
+
+    fn memstore() -> VersionedMonotree<MemoryStore, Blake3SmtHasher> {
+        VersionedMonotree::new("")
+    }
+
+    #[test]
+    fn past_states_are_readable_until_pruned() {
+        let mut state = memstore();
+        let (key, other) = ([1; 32], [2; 32]);
+        state.commit(1, &[(key, [10; 32])], &[]).unwrap();
+        state
+            .commit(3, &[(key, [11; 32]), (other, [12; 32])], &[])
+            .unwrap();
+        state.commit(4, &[], &[key]).unwrap();
+
+        assert!(state.get_at(0, &key).is_err());
+        assert_eq!(state.get_at(1, &key).unwrap(), Some([10; 32]));
+        assert_eq!(state.get_at(2, &key).unwrap(), Some([10; 32]));
+        assert_eq!(state.get_at(2, &other).unwrap(), None);
+        assert_eq!(state.get_at(3, &key).unwrap(), Some([11; 32]));
+        assert_eq!(state.get_at(4, &key).unwrap(), None);
+        assert_eq!(state.get_at(4, &other).unwrap(), Some([12; 32]));
+
+        let report = state.prune(3).unwrap();
+        assert_eq!(report.kept_heights, 2);
+        assert!(report.deleted_nodes > 0);
+        assert!(state.get_at(2, &key).is_err());
+        assert_eq!(state.get_at(3, &key).unwrap(), Some([11; 32]));
+        assert_eq!(state.get_at(4, &other).unwrap(), Some([12; 32]));
+    }
+
+    #[test]
+    fn blocks_are_committed_in_order() {
+        let mut state = memstore();
+        state.commit(2, &[([1; 32], [1; 32])], &[]).unwrap();
+        assert!(state.commit(2, &[([2; 32], [2; 32])], &[]).is_err());
+        assert!(state.commit(1, &[([2; 32], [2; 32])], &[]).is_err());
+        assert_eq!(state.latest_height(), Some(2));
+    }
+
+    #[test]
+    fn trees_open_at_once_keep_their_own_nodes() {
+        let mut first = memstore();
+        let mut second = memstore();
+        let mut third = VersionedMonotree::<SmtRockSdb, Blake3SmtHasher>::in_dir(BenchDir::new(
+            "versioned_monotree_test",
+        ));
+        let key = [1; 32];
+        first.commit(1, &[(key, [10; 32])], &[]).unwrap();
+        second.commit(1, &[(key, [20; 32])], &[]).unwrap();
+        third.commit(1, &[(key, [30; 32])], &[]).unwrap();
+        first.commit(2, &[(key, [11; 32])], &[]).unwrap();
+
+        assert!(first.prune(2).unwrap().deleted_nodes > 0);
+        assert_eq!(first.get_at(2, &key).unwrap(), Some([11; 32]));
+        assert_eq!(second.get_at(1, &key).unwrap(), Some([20; 32]));
+        assert_eq!(third.get_at(1, &key).unwrap(), Some([30; 32]));
+        assert_eq!(second.prune(1).unwrap().deleted_nodes, 0);
+        assert_eq!(third.prune(1).unwrap().deleted_nodes, 0);
+    }
+
+    // Every entry of the kept states is still found, reading all the nodes they reach
+    fn check_pruning(
+        mut state: VersionedMonotree<impl monotree::Database + 'static, Blake3SmtHasher>,
+    ) {
+        let mut workload = Workload::new(7);
+        let entries = workload.entries(200);
+        state.commit(0, &entries, &[]).unwrap();
+        let mut blocks = Vec::new();
+        for height in 1..=20 {
+            let block: Vec<(Hash, Hash)> = entries[height * 5..height * 5 + 10]
+                .iter()
+                .map(|(key, _)| (*key, workload.hash()))
+                .collect();
+            state.commit(height as u64, &block, &[]).unwrap();
+            blocks.push(block);
+        }
+
+        let report = state.prune(15).unwrap();
+        assert_eq!(report.kept_heights, 6);
+        assert!(report.deleted_nodes > 0);
+        for (height, block) in blocks.iter().enumerate().skip(14) {
+            for (key, leaf) in block {
+                assert_eq!(state.get_at(height as u64 + 1, key).unwrap(), Some(*leaf));
+            }
+        }
+
+        let again = state.prune(15).unwrap();
+        assert_eq!(again.deleted_nodes, 0);
+        assert_eq!(again.kept_nodes, report.kept_nodes);
+        let latest = state.prune(20).unwrap();
+        assert_eq!(latest.kept_heights, 1);
+        assert!(latest.kept_nodes < report.kept_nodes);
+        let leaves: BTreeMap<Hash, Hash> = entries
+            .into_iter()
+            .chain(blocks.into_iter().flatten())
+            .collect();
+        for (key, leaf) in leaves.iter() {
+            assert_eq!(state.get_at(20, key).unwrap(), Some(*leaf));
+        }
+    }
+
+    #[test]
+    fn pruned_memstore_keeps_the_recent_states() {
+        check_pruning(memstore());
+    }
+
+    #[test]
+    fn pruned_rocksdb_keeps_the_recent_states() {
+        check_pruning(VersionedMonotree::<SmtRockSdb, _>::in_dir(BenchDir::new(
+            "versioned_monotree_test",
+        )));
+    }
+}
@@ -0,0 +1,72 @@
+use std::sync::OnceLock;
+
+use monotree::Hash;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+/// Environment variable fixing the seed of the workloads, to re-run a benchmark on the same keys
+pub const SEED_ENV: &str = "SMT_BENCH_SEED";
+
+static SEED: OnceLock<u64> = OnceLock::new();
+
+/// Seed of this run, taken from `SEED_ENV` or drawn and printed on first use
+pub fn workload_seed() -> u64 {
+    *SEED.get_or_init(|| {
+        let seed = match std::env::var(SEED_ENV) {
+            Ok(seed) => seed
+                .parse()
+                .unwrap_or_else(|_| panic!("{} must be a u64, found {:?}", SEED_ENV, seed)),
+            Err(_) => rand::thread_rng().gen(),
+        };
+        println!("workload seed {}, re-run with {}={}", seed, SEED_ENV, seed);
+        seed
+    })
+}
+
+/// Stream of keys and values, the same for every workload built from the same seed
+#[derive(Clone)]
+pub struct Workload {
+    rng: ChaCha8Rng,
+}
+
+impl Workload {
+    pub fn new(seed: u64) -> Workload {
+        Workload {
+            rng: ChaCha8Rng::seed_from_u64(seed),
+        }
+    }
+
+    /// Workload of this run, every benchmark starts again from the beginning of the stream
+    pub fn seeded() -> Workload {
+        Workload::new(workload_seed())
+    }
+
+    pub fn hash(&mut self) -> Hash {
+        self.rng.gen()
+    }
+
+    /// A key and the leaf to insert at it
+    pub fn entry(&mut self) -> (Hash, Hash) {
+        (self.hash(), self.hash())
+    }
+
+    pub fn entries(&mut self, nb: usize) -> Vec<(Hash, Hash)> {
+        (0..nb).map(|_| self.entry()).collect()
+    }
+
+    /// Generator of the stream, for the random choices that go with the keys
+    pub fn rng(&mut self) -> &mut ChaCha8Rng {
+        &mut self.rng
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Workload;
+
+    #[test]
+    fn same_seed_same_stream() {
+        assert_eq!(Workload::new(42).entries(10), Workload::new(42).entries(10));
+        assert_ne!(Workload::new(42).entries(10), Workload::new(43).entries(10));
+    }
+}